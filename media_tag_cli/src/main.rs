@@ -1,194 +1,2483 @@
-use std::process::exit;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, IsTerminal, Write};
+use std::path::{MAIN_SEPARATOR, Path};
+use std::process;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{env, path::PathBuf};
 
-use clap::{Parser, Subcommand};
-use media_tag_lib::MediaTag;
+use clap::{Parser, Subcommand, ValueEnum};
+use media_tag_lib::{
+    ConflictPolicy, DEFAULT_BATCH_SIZE, DEFAULT_MAX_RETRIES, HashAlgo, LibraryLock, MediaTag,
+    MediaTags, Medium, MissingTag, SearchQuery, TagCase, TagOrder, WalkOptions, glob_matches,
+    validate_tag_name,
+};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    /// Write the output of listing commands (search, status, export, ...) to a file instead of
+    /// stdout. The file is only replaced once the command finished successfully.
+    #[arg(short, long, global = true, value_name = "FILE")]
+    output: Option<PathBuf>,
+
+    /// How long to wait for another process to release its lock on the database
+    #[arg(long, global = true, value_name = "MS")]
+    busy_timeout: Option<u64>,
+
+    /// How often adding or removing tags is retried when the database stays busy after the busy
+    /// timeout, waiting twice as long before every retry
+    #[arg(long, global = true, value_name = "N", default_value_t = DEFAULT_MAX_RETRIES)]
+    max_retries: u32,
+
+    /// Print the executed SQL statements with their duration and how paths are resolved to stderr
+    #[arg(long, global = true)]
+    debug: bool,
+
+    /// Store paths as given instead of resolving symbolic links, so files on unmounted volumes
+    /// can be tagged (the same file reached through different paths is not detected)
+    #[arg(long, global = true)]
+    no_canonicalize: bool,
+
+    /// Print tag names in their color: auto only colors output to a terminal and respects
+    /// NO_COLOR
+    #[arg(
+        long = "color",
+        id = "color_choice",
+        global = true,
+        value_enum,
+        value_name = "WHEN",
+        default_value_t = ColorChoice::Auto
+    )]
+    color: ColorChoice,
+
+    /// Print the paths of tracked files below this directory instead of the library root, e.g.
+    /// to look at a copy of the database while the files live somewhere else (stored paths are
+    /// not changed)
+    #[arg(long, global = true, value_name = "DIR")]
+    output_root: Option<PathBuf>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
-#[derive(Subcommand, Debug)]
-enum Commands {
-    /// Initialize a media tag directory (create the database file)
-    Init,
-    /// Create a new tag
-    CreateTag { tags: Vec<String> },
-    /// Print all tags
-    ShowTags,
-    /// Search tagged files
-    Search {
-        /// Look for files containing any of the provided tags
-        #[arg(short, long)]
-        any: bool,
+#[derive(Subcommand, Debug)]
+enum Commands {
+    /// Initialize a media tag directory (create the database file)
+    Init,
+    /// Upgrade the database schema after installing a new version of media tag
+    Migrate,
+    /// Create a new tag
+    CreateTag {
+        #[arg(value_parser = parse_tag_name)]
+        tags: Vec<String>,
+
+        /// A display color for the new tags: black, red, green, yellow, blue, magenta, cyan, white
+        /// or a hex color like `#ff8800`
+        #[arg(long, value_name = "COLOR")]
+        tag_color: Option<String>,
+
+        /// A description of the new tags
+        #[arg(long)]
+        description: Option<String>,
+    },
+    /// Print all tags
+    ShowTags {
+        /// Group tags by the prefix before the given separator (e.g. `:` for `project:alpha`)
+        #[arg(long, value_name = "SEP")]
+        group_by: Option<String>,
+
+        /// Print the aliases of each tag in parentheses
+        #[arg(long, conflicts_with = "group_by")]
+        with_aliases: bool,
+
+        /// Only print tags starting with PREFIX
+        #[arg(long, conflicts_with = "with_aliases")]
+        prefix: Option<String>,
+
+        /// The order to print tags in
+        #[arg(long, value_enum, default_value_t = TagSorting::Id, conflicts_with = "with_aliases")]
+        sort: TagSorting,
+
+        /// Print at most N tags
+        #[arg(long, value_name = "N", conflicts_with = "with_aliases")]
+        limit: Option<usize>,
+
+        /// Skip the first N tags
+        #[arg(
+            long,
+            value_name = "N",
+            default_value_t = 0,
+            conflicts_with = "with_aliases"
+        )]
+        offset: usize,
+    },
+    /// Print how tag names are written when tags are created and looked up, or set it
+    TagCase {
+        #[arg(value_enum)]
+        case: Option<TagCasing>,
+    },
+    /// Make a tag also available under another name
+    Alias { alias: String, tag: String },
+    /// Search tagged files
+    Search {
+        #[command(flatten)]
+        args: SearchArgs,
+
+        /// Print at most N files
+        #[arg(long, value_name = "N", default_value_t = 1000)]
+        max_results: usize,
+
+        /// Print all files, however many there are
+        #[arg(long, conflicts_with = "max_results")]
+        all: bool,
+
+        #[command(flatten)]
+        output: PathOutput,
+
+        /// Search the library in DIR (its root directory or database file) instead of the
+        /// current one; repeat to search several libraries and prefix every file with the name of
+        /// its library
+        #[arg(long = "library", value_name = "DIR")]
+        libraries: Vec<PathBuf>,
+    },
+    /// Save a search under a name to run it later with `run-query`
+    SaveQuery {
+        name: String,
+
+        /// The tags to look for
+        queries: Vec<String>,
+
+        /// The tags to exclude
+        #[arg(long = "not", num_args = 1..)]
+        exclude: Vec<String>,
+
+        /// Look for files containing any of the tags instead of all of them
+        #[arg(short, long)]
+        any: bool,
+    },
+    /// Run a saved search
+    RunQuery {
+        name: String,
+
+        /// How to print the paths of files
+        #[arg(long, value_enum, default_value_t = PathStyle::Absolute)]
+        path_style: PathStyle,
+    },
+    /// List the saved searches
+    ListQueries,
+    /// Delete a saved search
+    DeleteQuery { name: String },
+    /// Get a list of all tagged files along with their tags (sorted alphabetically)
+    Status {
+        /// Also list files in the library directory that are not tracked, prefixed with `?`
+        #[arg(short = 'u', long)]
+        include_untracked: bool,
+
+        /// Also print the notes attached to files
+        #[arg(short, long)]
+        verbose: bool,
+
+        /// Skip files without tags
+        #[arg(long, conflicts_with = "only_untagged")]
+        exclude_untagged: bool,
+
+        /// Only list files without tags
+        #[arg(long)]
+        only_untagged: bool,
+
+        /// Print the number of tags in front of every file
+        #[arg(short, long)]
+        count: bool,
+
+        /// Print the rating in front of every file (`-` if it was not rated)
+        #[arg(long)]
+        show_rating: bool,
+
+        /// Print the files as a tree of directories below the library root
+        #[arg(long, conflicts_with_all = ["count", "show_rating", "path_style", "relative", "paths", "print0"])]
+        tree: bool,
+
+        /// The separator between the tags of a file
+        #[arg(long, value_name = "SEP", default_value = ",")]
+        tag_sep: String,
+
+        #[command(flatten)]
+        output: PathOutput,
+    },
+    /// Add files to the database without tagging them
+    Track { files: Vec<PathBuf> },
+    /// Remove files from the database along with their tags, note and rating (the files are not
+    /// touched and may already be deleted)
+    Forget {
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Tag one or more files with one or more tags (`#<id>` refers to a tag by its id)
+    Add {
+        #[command(flatten)]
+        args: TagArgs,
+
+        /// Create tags that do not exist yet
+        #[arg(long, overrides_with = "no_create")]
+        create: bool,
+
+        /// Fail on tags that do not exist, so typos are caught (the default)
+        #[arg(long, overrides_with = "create")]
+        no_create: bool,
+    },
+    /// Remove one or more tags from one or more files
+    Remove {
+        #[command(flatten)]
+        args: TagArgs,
+
+        /// Show how many tags would be removed and ask before removing them
+        #[arg(long)]
+        confirm: bool,
+
+        /// Do not ask before removing many tags at once
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Add or remove tags on all files matching a search
+    Retag {
+        /// The tags to add
+        #[arg(long, num_args = 1.., value_name = "TAG", value_parser = parse_tag_name)]
+        add: Vec<String>,
+
+        /// The tags to remove
+        #[arg(long, required_unless_present = "add", num_args = 1.., value_name = "TAG")]
+        remove: Vec<String>,
+
+        /// Create tags to add that do not exist yet
+        #[arg(long, requires = "add")]
+        create: bool,
+
+        /// The tags the files have to have
+        #[arg(long = "query", required = true, num_args = 1.., value_name = "TAG")]
+        queries: Vec<String>,
+
+        /// The tags the files must not have
+        #[arg(long = "not", num_args = 1.., value_name = "TAG")]
+        exclude: Vec<String>,
+
+        /// Match files with any of the query tags instead of all of them
+        #[arg(short, long)]
+        any: bool,
+    },
+    /// Add all tags of one file to another
+    CopyTags { src: PathBuf, dst: PathBuf },
+    /// Write a .tags sidecar file listing the tags next to every tagged file
+    ExportSidecars,
+    /// Go through the untagged files of a directory and enter their tags one file at a time
+    TagInteractive {
+        /// The directory to walk (honors .mediatagignore)
+        #[arg(default_value = ".")]
+        dir: PathBuf,
+    },
+    /// Find tags that are probably spelling variants of each other
+    LintTags {
+        /// Minimum similarity between 0 and 1 for two tags to be reported
+        #[arg(long, default_value_t = 0.8)]
+        threshold: f64,
+    },
+    /// Delete a tag and remove it from all files
+    DeleteTag {
+        name: String,
+
+        /// Only print how many files would lose the tag
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Delete all tags that are not assigned to any file
+    PruneTags {
+        /// Only print the tags that would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Rename all tags matching a pattern, e.g. `rename-tags 'proj-*' 'project/*'`
+    RenameTags {
+        /// Tag names to rename; `*` matches any part of a name
+        pattern: String,
+
+        /// The new names; every `*` is replaced with the part matched by the corresponding `*` of
+        /// the pattern
+        replacement: String,
+
+        /// Only print the tags that would be renamed
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// List tracked files with identical contents
+    Duplicates {
+        /// The hash algorithm (sha256, or blake3 if built with the `blake3` feature)
+        #[arg(long, value_parser = parse_hash_algo, default_value = "sha256")]
+        algo: HashAlgo,
+
+        /// Hash all files again, e.g. after they were modified
+        #[arg(long)]
+        rehash: bool,
+
+        /// Hash N files at once. Worthwhile on SSDs (up to the number of cores), while spinning
+        /// disks are usually fastest with 1
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        threads: usize,
+    },
+    /// Remove duplicate tag assignments left by older versions or manual edits
+    Repair,
+    /// Record the modification times of files tracked by older versions, which have none yet
+    UpdateMtimes {
+        /// Record the current modification time of every tracked file
+        #[arg(long)]
+        all: bool,
+    },
+    /// List files in the library directory that are not tracked yet
+    Untracked {
+        /// Descend at most N directory levels below the library root
+        #[arg(long, value_name = "N")]
+        depth: Option<usize>,
+
+        /// Descend into symbolic links to directories (each directory is visited only once)
+        #[arg(long)]
+        follow_symlinks: bool,
+    },
+    /// List the files with the most (or least) tags
+    Top {
+        /// List the least tagged files instead (including untagged ones)
+        #[arg(long, conflicts_with = "most")]
+        least: bool,
+
+        /// List the most tagged files (default)
+        #[arg(long)]
+        most: bool,
+
+        /// How many files to list
+        #[arg(short = 'n', value_name = "N", default_value_t = 10)]
+        limit: usize,
+    },
+    /// List the files sharing tags with a file, most shared tags first
+    Similar {
+        file: PathBuf,
+
+        /// Only list files sharing at least N tags
+        #[arg(long = "min", value_name = "N", default_value_t = 1)]
+        min_shared: usize,
+    },
+    /// Print statistics about the library
+    Stats,
+    /// Print how many files have each tag as a bar chart
+    Distribution,
+    /// Print every combination of tags that occurs on files, with the number of files that have
+    /// exactly these tags, most frequent first
+    Combinations {
+        /// Only print combinations that at least N files have
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        min: i64,
+    },
+    /// Print how many files have a tag, when it was first and last used and some of its files
+    TagInfo { name: String },
+    /// Print all tag names, most used first, for shell completion scripts. This is an internal
+    /// interface whose output format may change between versions.
+    #[command(hide = true, name = "__complete-tags")]
+    CompleteTags,
+    /// Print the note of a file, or set it when a text is given
+    Note {
+        file: PathBuf,
+
+        /// The new note (an empty text removes the note)
+        text: Option<String>,
+    },
+    /// Rate a file from 0 to 5
+    Rate { file: PathBuf, rating: u8 },
+    /// Print a Graphviz graph of which tags occur together
+    Graph {
+        /// Only connect tags that share at least N files
+        #[arg(long, value_name = "N", default_value_t = 1)]
+        min: i64,
+    },
+    /// Print the id, path, tags, note, rating and modification time of a file
+    Show {
+        file: PathBuf,
+
+        /// Print the file as a JSON object
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show when each tag was added to a file
+    History { file: PathBuf },
+    /// List files whose stored path differs from the path on disk only by case
+    VerifyCase,
+    /// Rename or move a file on disk and keep its tags
+    Rename { old: PathBuf, new: PathBuf },
+    /// Update the paths of all files after moving a directory, e.g. `relocate photos/2023 archive/2023`
+    Relocate {
+        /// The old directory, relative to the library root
+        old: String,
+
+        /// The new directory, relative to the library root (empty for the root itself)
+        new: String,
+    },
+    /// Import files and their tags from a file written by `export`
+    Import {
+        file: PathBuf,
+
+        /// The format of the file (detected from its extension by default)
+        #[arg(short, long, value_enum)]
+        format: Option<ExportFormat>,
+
+        /// What to do with files that already have tags
+        #[arg(long, value_enum, default_value_t = OnConflict::Skip)]
+        on_conflict: OnConflict,
+
+        /// How many files are read and inserted at once (CSV and NDJSON only). Lower values need
+        /// less memory, higher values are faster
+        #[arg(long, value_name = "N", default_value_t = DEFAULT_BATCH_SIZE)]
+        batch_size: usize,
+
+        /// Abort the import at the first malformed line instead of skipping it (NDJSON only)
+        #[arg(long)]
+        strict: bool,
+    },
+    /// Make the tags of the files listed in a TOML spec (`[media]` with `"path" = ["tag", ...]`)
+    /// exactly the listed ones, creating missing tags
+    Apply {
+        spec: PathBuf,
+
+        /// Only print the changes that would be made
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Export all files along with their tags
+    Export {
+        /// The output format
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+
+        /// How many files are loaded from the database at once (CSV and NDJSON only). Lower values
+        /// need less memory, higher values are faster
+        #[arg(long, value_name = "N", default_value_t = DEFAULT_BATCH_SIZE)]
+        batch_size: usize,
+    },
+}
+
+// Filters of `search`, applied to each library that is searched.
+#[derive(clap::Args, Clone, Debug)]
+struct SearchArgs {
+    /// Look for files containing any of the provided tags (default if MEDIA_TAG_SEARCH_ANY=1)
+    #[arg(short, long, conflicts_with = "every")]
+    any: bool,
+
+    /// Look for files containing all of the provided tags (overrides MEDIA_TAG_SEARCH_ANY)
+    #[arg(short, long)]
+    every: bool,
+
+    /// The tags you are looking for
+    #[arg(num_args = 1.., value_parser = parse_tag_name)]
+    queries: Vec<String>,
+
+    /// The tags you want to exclude
+    #[arg(long = "not", num_args = 1.., value_parser = parse_tag_name)]
+    exclude: Vec<String>,
+
+    /// Also look for the tags listed in FILE, one per line (like `--tag-file` of `add`)
+    #[arg(long, value_name = "FILE")]
+    query_file: Vec<PathBuf>,
+
+    /// Also exclude the tags listed in FILE, one per line
+    #[arg(long, value_name = "FILE")]
+    exclude_file: Vec<PathBuf>,
+
+    /// Match tag names case-insensitively
+    #[arg(short, long)]
+    ignore_case: bool,
+
+    /// Only list files with exactly the given tags and no others (without tags: untagged
+    /// files)
+    #[arg(long, conflicts_with_all = ["any", "exclude", "exclude_file", "ignore_case"])]
+    exact: bool,
+
+    /// Only list files rated N or higher
+    #[arg(long, value_name = "N")]
+    min_rating: Option<u8>,
+
+    /// Only list files last modified at or after TIME: a date like `2023-07`, `2023-07-15` or
+    /// `2023-07-15 18:30` (UTC), or how long ago like `90m` or `2w` (units s, m, h, d, w)
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    modified_after: Option<i64>,
+
+    /// Only list files last modified before TIME (same formats as --modified-after)
+    #[arg(long, value_name = "TIME", value_parser = parse_time)]
+    modified_before: Option<i64>,
+
+    /// Also list files whose modification time is unknown when filtering by it (see
+    /// `update-mtimes`)
+    #[arg(long)]
+    include_unknown_mtime: bool,
+
+    /// Only list files whose path relative to the library root contains TEXT
+    #[arg(long, value_name = "TEXT")]
+    path_contains: Option<String>,
+
+    /// Only list files whose path relative to the library root matches GLOB (`*` and `?` do
+    /// not match `/`, `**` does)
+    #[arg(long, value_name = "GLOB")]
+    path_glob: Option<String>,
+
+    /// Only list files with the extension EXT, e.g. `mp4` (case-insensitive; repeat to list files
+    /// with any of several extensions)
+    #[arg(long = "ext", value_name = "EXT")]
+    extensions: Vec<String>,
+}
+
+// Files and tags of `add`/`remove`. Positional arguments that exist on disk are files and all others
+// are tags, unless `--` is used.
+#[derive(clap::Args, Debug)]
+struct TagArgs {
+    /// Files and tags (arguments that exist on disk are treated as files)
+    parameters: Vec<String>,
+
+    /// Treat the argument as a file
+    #[arg(long = "path", value_name = "PATH")]
+    paths: Vec<PathBuf>,
+
+    /// Also read files from stdin, one per line
+    #[arg(long, conflicts_with = "stdin0")]
+    stdin: bool,
+
+    /// Also read files from stdin separated by NUL bytes, e.g. from `find -print0`
+    #[arg(long)]
+    stdin0: bool,
+
+    /// Treat the argument as a tag
+    #[arg(long = "tag", value_name = "TAG", value_parser = parse_tag_name)]
+    tags: Vec<String>,
+
+    /// Tags; all positional arguments before `--` are then treated as files
+    #[arg(last = true, value_name = "TAGS", value_parser = parse_tag_name)]
+    trailing_tags: Vec<String>,
+
+    /// Also use the tags listed in FILE, one per line (lines starting with `#` are comments)
+    #[arg(long, value_name = "FILE")]
+    tag_file: Vec<PathBuf>,
+
+    /// Apply to all files inside the given directories (honors .mediatagignore)
+    #[arg(short, long)]
+    recursive: bool,
+
+    /// Descend at most N directory levels (0 = only files directly inside the given directories)
+    #[arg(long, value_name = "N", requires = "recursive")]
+    depth: Option<usize>,
+
+    /// Descend into symbolic links to directories (each directory is visited only once)
+    #[arg(long, requires = "recursive")]
+    follow_symlinks: bool,
+
+    /// Resolve relative file arguments against DIR instead of the current directory
+    #[arg(long, value_name = "DIR")]
+    relative_to: Option<PathBuf>,
+
+    /// Do not print a summary at the end
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ExportFormat {
+    /// Comma separated values with the columns `path,tags` (tags are separated by `;`), after a
+    /// `# media_tag export format <version>` line
+    Csv,
+    /// A `media` table mapping every file to a list of its tags, along with a list of all `tags`
+    Toml,
+    /// One JSON object `{"path": ..., "tags": [...]}` per line
+    Ndjson,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+// How `search` and `status` print the paths of files. `--paths` makes both print exactly the same
+// thing, so scripts can rely on it whichever command they call.
+#[derive(clap::Args, Clone, Copy, Debug)]
+struct PathOutput {
+    /// How to print the paths of files
+    #[arg(long, value_enum, default_value_t = PathStyle::Absolute)]
+    path_style: PathStyle,
+
+    /// Print paths relative to the library root (short for `--path-style stored`)
+    #[arg(long, conflicts_with = "path_style")]
+    relative: bool,
+
+    /// Only print the path of every file, one per line, without tags or other decoration
+    #[arg(long)]
+    paths: bool,
+
+    /// Like `--paths`, but end every path with a NUL byte instead of a newline, e.g. for
+    /// `xargs -0`
+    #[arg(long)]
+    print0: bool,
+}
+
+impl PathOutput {
+    fn style(&self) -> PathStyle {
+        if self.relative {
+            PathStyle::Stored
+        } else {
+            self.path_style
+        }
+    }
+
+    fn paths_only(&self) -> bool {
+        self.paths || self.print0
+    }
+
+    /// Prints a path on its own line, or terminated by a NUL byte with `--print0`.
+    fn print(&self, out: &mut Output, path: &str) {
+        let terminator = if self.print0 { '\0' } else { '\n' };
+        write!(out, "{path}{terminator}").unwrap_or_else(|err| print_error_and_exit(err));
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum PathStyle {
+    /// Relative to the library root, as stored in the database
+    Stored,
+    /// The library root joined with the stored path
+    Absolute,
+    /// The absolute path with symbolic links resolved (files that no longer exist are skipped)
+    Canonical,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum TagCasing {
+    /// Keep tag names as given
+    AsIs,
+    /// Lowercase tag names (`Summer Vacation` -> `summer vacation`)
+    Lower,
+    /// Lowercase tag names and join words with `-` (`Summer Vacation` -> `summer-vacation`)
+    Kebab,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum TagSorting {
+    /// Alphabetically
+    Name,
+    /// In the order the tags were created
+    Id,
+    /// Most used first
+    Count,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum OnConflict {
+    /// Keep the existing tags and add the imported ones
+    Skip,
+    /// Abort the import if a file already has one of the imported tags
+    Error,
+    /// Replace the tags of every imported file with the imported ones
+    Replace,
+}
+
+const DB_FILENAME: &str = ".media_tag.db";
+
+/// The library lock held by the running command, see [`needs_lock`]. It lives in a static so that
+/// [`exit`] can release it, as `process::exit` skips destructors.
+static LIBRARY_LOCK: Mutex<Option<LibraryLock>> = Mutex::new(None);
+
+/// The temporary file an `--output` file is written to until the command finished, see
+/// [`Output`]. Like [`LIBRARY_LOCK`], it lives in a static so that [`exit`] can delete it.
+static OUTPUT_TEMP_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Makes `search` look for any instead of all of the given tags unless `--every` is passed.
+const SEARCH_ANY_ENV: &str = "MEDIA_TAG_SEARCH_ANY";
+
+/// Number of file/tag pairs from which `remove` asks for confirmation and switches to a single bulk
+/// transaction.
+const BULK_THRESHOLD: usize = 100;
+
+/// Extensions that mark an argument as a file even if it does not exist (anymore).
+const MEDIA_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "heic", "tiff", "bmp", "svg", "mp4", "mkv", "webm", "avi",
+    "mov", "wmv", "flv", "mp3", "flac", "ogg", "opus", "wav", "m4a", "aac", "pdf", "epub",
+];
+
+/// `println!` for the primary output of a command, which goes to the `--output` file if given.
+macro_rules! outln {
+    ($out:expr $(, $arg:expr)* $(,)?) => {
+        writeln!($out $(, $arg)*).unwrap_or_else(|err| print_error_and_exit(err))
+    };
+}
+
+/// Destination of the primary output of a command.
+enum Output {
+    Stdout(io::Stdout),
+    /// Output is buffered into a temporary file next to `path`, which is renamed to `path` by
+    /// [`Output::finish`] and deleted by [`exit`] if the command fails. A failed command therefore
+    /// never leaves a truncated file behind.
+    File {
+        writer: BufWriter<File>,
+        temp_path: PathBuf,
+        path: PathBuf,
+    },
+}
+
+impl Output {
+    fn create(path: &Path) -> io::Result<Self> {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file path"))?;
+        let mut temp_name = OsString::from(".");
+        temp_name.push(file_name);
+        temp_name.push(".tmp");
+        let temp_path = path.with_file_name(temp_name);
+
+        let file = File::create(&temp_path)?;
+        *OUTPUT_TEMP_PATH.lock().unwrap_or_else(|e| e.into_inner()) = Some(temp_path.clone());
+        Ok(Self::File {
+            writer: BufWriter::new(file),
+            temp_path,
+            path: path.to_path_buf(),
+        })
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Self::Stdout(mut stdout) => stdout.flush(),
+            Self::File {
+                writer,
+                temp_path,
+                path,
+            } => {
+                let file = writer.into_inner().map_err(|err| err.into_error())?;
+                file.sync_all()?;
+                fs::rename(temp_path, path)?;
+                OUTPUT_TEMP_PATH
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .take();
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Stdout(stdout) => stdout.write(buf),
+            Self::File { writer, .. } => writer.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Stdout(stdout) => stdout.flush(),
+            Self::File { writer, .. } => writer.flush(),
+        }
+    }
+}
+
+/// A file given to `add`/`remove`.
+enum Target {
+    /// A file that exists on disk
+    Path(PathBuf),
+    /// A tracked medium whose file was moved, found by its file name
+    Moved(Medium),
+}
+
+impl Target {
+    fn path(&self) -> &Path {
+        match self {
+            Self::Path(path) => path,
+            Self::Moved(medium) => &medium.path,
+        }
+    }
+}
+
+fn parse_hash_algo(name: &str) -> Result<HashAlgo, String> {
+    HashAlgo::from_name(name).ok_or_else(|| {
+        let available: Vec<&str> = HashAlgo::ALL.iter().map(|algo| algo.name()).collect();
+        format!("available algorithms: {}", available.join(", "))
+    })
+}
+
+/// Rejects tag names that could never be valid before any database work is done.
+fn parse_tag_name(name: &str) -> Result<String, String> {
+    validate_tag_name(name).map_err(|err| err.to_string())?;
+    Ok(name.to_string())
+}
+
+/// Parses a point in time for `--modified-after`/`--modified-before` into a unix timestamp: a UTC
+/// date (`2023`, `2023-07`, `2023-07-15`, optionally followed by `18:30` or `18:30:15`), or a
+/// duration before now (`30s`, `90m`, `12h`, `7d`, `2w`).
+fn parse_time(time: &str) -> Result<i64, String> {
+    const EXPECTED: &str =
+        "expected a date like 2023-07-15 or 2023-07-15 18:30, or a duration like 7d";
+
+    let time = time.trim();
+    if let Some(unit) = time.chars().last().filter(char::is_ascii_alphabetic) {
+        let seconds = match unit {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 7 * 86400,
+            _ => {
+                return Err(format!(
+                    "unknown unit '{unit}', expected one of s, m, h, d, w"
+                ));
+            }
+        };
+        let amount: i64 = time[..time.len() - 1]
+            .parse()
+            .map_err(|_| EXPECTED.to_string())?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|err| err.to_string())?
+            .as_secs();
+        return Ok(i64::try_from(now).unwrap_or(i64::MAX) - amount.saturating_mul(seconds));
+    }
+
+    let (date, clock) = time
+        .split_once([' ', 'T'])
+        .map_or((time, None), |(date, clock)| (date, Some(clock)));
+    let numbers = |text: &str, separator: char| -> Result<Vec<i64>, String> {
+        text.split(separator)
+            .map(|part| part.parse().map_err(|_| EXPECTED.to_string()))
+            .collect()
+    };
+
+    let date = numbers(date, '-')?;
+    let (year, month, day) = match date[..] {
+        [year] => (year, 1, 1),
+        [year, month] => (year, month, 1),
+        [year, month, day] => (year, month, day),
+        _ => return Err(EXPECTED.to_string()),
+    };
+    let clock = match clock {
+        Some(clock) => numbers(clock, ':')?,
+        None => Vec::new(),
+    };
+    let (hour, minute, second) = match clock[..] {
+        [] => (0, 0, 0),
+        [hour, minute] => (hour, minute, 0),
+        [hour, minute, second] => (hour, minute, second),
+        _ => return Err(EXPECTED.to_string()),
+    };
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || !(0..24).contains(&hour)
+        || !(0..60).contains(&minute)
+        || !(0..60).contains(&second)
+    {
+        return Err(EXPECTED.to_string());
+    }
+
+    Ok(days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Parses the `#<id>` form of a tag argument.
+fn parse_tag_id(tag: &str) -> Option<i64> {
+    tag.strip_prefix('#')?.parse().ok()
+}
+
+fn looks_like_path(parameter: &str) -> bool {
+    parameter.contains('/')
+        || parameter.contains(MAIN_SEPARATOR)
+        || Path::new(parameter)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| {
+                MEDIA_EXTENSIONS
+                    .iter()
+                    .any(|known| known.eq_ignore_ascii_case(ext))
+            })
+}
+
+/// Resolves an argument known to be a file. Files that no longer exist are looked up among the
+/// tracked media by their file name.
+fn resolve_target(media_tag: &MediaTag, path: PathBuf) -> Target {
+    if path.exists() {
+        return Target::Path(path);
+    }
+    let medium = media_tag
+        .find_medium_by_file_name(&path)
+        .unwrap_or_else(|err| print_error_and_exit(err));
+    Target::Moved(medium)
+}
+
+fn parse_args(media_tag: &MediaTag, args: TagArgs) -> (Vec<Target>, Vec<String>) {
+    let base = |path: PathBuf| match &args.relative_to {
+        Some(dir) => dir.join(path),
+        None => path,
+    };
+    let mut targets: Vec<Target> = args
+        .paths
+        .into_iter()
+        .map(|path| resolve_target(media_tag, base(path)))
+        .collect();
+    if args.stdin || args.stdin0 {
+        let separator = if args.stdin0 { b'\0' } else { b'\n' };
+        targets.extend(
+            read_stdin_paths(separator)
+                .into_iter()
+                .map(|path| resolve_target(media_tag, base(path))),
+        );
+    }
+    let mut tags = args.tags;
+
+    if !args.trailing_tags.is_empty() {
+        targets.extend(
+            args.parameters
+                .into_iter()
+                .map(|parameter| resolve_target(media_tag, base(PathBuf::from(parameter)))),
+        );
+        tags.extend(args.trailing_tags);
+    } else {
+        for parameter in args.parameters {
+            let path = base(PathBuf::from(&parameter));
+            if path.exists() || looks_like_path(&parameter) {
+                targets.push(resolve_target(media_tag, path));
+            } else {
+                tags.push(parameter);
+            }
+        }
+    }
+
+    if args.recursive {
+        let options = WalkOptions {
+            max_depth: args.depth,
+            follow_symlinks: args.follow_symlinks,
+        };
+        targets = targets
+            .into_iter()
+            .flat_map(|target| match target {
+                Target::Path(path) if path.is_dir() => media_tag
+                    .walk_files(&path, &options)
+                    .unwrap_or_else(|err| print_error_and_exit(err))
+                    .into_iter()
+                    .map(Target::Path)
+                    .collect(),
+                target => vec![target],
+            })
+            .collect();
+    }
+
+    for file in &args.tag_file {
+        tags.extend(read_tag_file(file));
+    }
+    let mut seen = HashSet::new();
+    tags.retain(|tag| seen.insert(tag.clone()));
+
+    (targets, tags)
+}
+
+/// Reads paths from stdin separated by `separator`. Empty entries, like the one after a trailing
+/// separator, are skipped.
+fn read_stdin_paths(separator: u8) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for entry in io::stdin().lock().split(separator) {
+        let mut entry = entry.unwrap_or_else(|err| print_error_and_exit(err));
+        if separator == b'\n' && entry.last() == Some(&b'\r') {
+            entry.pop();
+        }
+        if !entry.is_empty() {
+            paths.push(path_from_bytes(entry));
+        }
+    }
+    paths
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Reads tag names from a file with one tag per line. Surrounding whitespace is trimmed, and
+/// blank lines and lines starting with `#` are skipped.
+fn read_tag_file(path: &Path) -> Vec<String> {
+    let contents = fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!("failed to read tag file '{}'", path.display());
+        print_error_and_exit(err)
+    });
+
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Adds the tags listed in the `--query-file`s and `--exclude-file`s of a search to its tags.
+fn resolve_tag_files(args: &mut SearchArgs) {
+    for file in &args.query_file {
+        args.queries.extend(read_tag_file(file));
+    }
+    for file in &args.exclude_file {
+        args.exclude.extend(read_tag_file(file));
+    }
+}
+
+/// Returns the media of `media_tag` matching the filters of a search, see [`resolve_tag_files`]
+/// for the tag files.
+fn search_media(media_tag: &MediaTag, args: &SearchArgs) -> Vec<Medium> {
+    let SearchArgs {
+        any,
+        every,
+        ref queries,
+        ref exclude,
+        ignore_case,
+        exact,
+        min_rating,
+        modified_after,
+        modified_before,
+        include_unknown_mtime,
+        ref path_contains,
+        ref path_glob,
+        ref extensions,
+        ..
+    } = *args;
+    let any = any || (!every && search_any_default());
+    let queries: Vec<&str> = queries.iter().map(String::as_str).collect();
+    let exclude: Vec<&str> = exclude.iter().map(String::as_str).collect();
+    let filter_mtime = modified_after.is_some() || modified_before.is_some();
+    let mut media = if exact {
+        media_tag.media_with_exact_tags(&queries)
+    } else if ignore_case {
+        media_tag.query_ignore_case(&queries, &exclude, any)
+    } else {
+        media_tag.query(&queries, &exclude, any)
+    }
+    .unwrap_or_else(|err| print_error_and_exit(err));
+    if let Some(min_rating) = min_rating {
+        media.retain(|medium| medium.rating.is_some_and(|rating| rating >= min_rating));
+    }
+    if filter_mtime {
+        let modified: HashSet<i64> = media_tag
+            .media_modified_between(
+                modified_after.unwrap_or(i64::MIN),
+                modified_before.unwrap_or(i64::MAX),
+            )
+            .unwrap_or_else(|err| print_error_and_exit(err))
+            .iter()
+            .map(|medium| medium.id)
+            .collect();
+        media.retain(|medium| {
+            modified.contains(&medium.id) || (include_unknown_mtime && medium.mtime.is_none())
+        });
+    }
+    if path_contains.is_some() || path_glob.is_some() {
+        media.retain(|medium| {
+            let path = medium.relative.to_string_lossy();
+            path_contains
+                .as_ref()
+                .is_none_or(|text| path.contains(text))
+                && path_glob
+                    .as_ref()
+                    .is_none_or(|glob| glob_matches(glob, &path))
+        });
+    }
+    if !extensions.is_empty() {
+        media.retain(|medium| {
+            medium.relative.extension().is_some_and(|ext| {
+                let ext = ext.to_string_lossy();
+                extensions
+                    .iter()
+                    .any(|wanted| ext.eq_ignore_ascii_case(wanted.trim_start_matches('.')))
+            })
+        });
+    }
+    media
+}
+
+fn search_any_default() -> bool {
+    env::var(SEARCH_ANY_ENV)
+        .is_ok_and(|value| matches!(value.to_lowercase().as_str(), "1" | "true" | "yes"))
+}
+
+/// Counts how many tag assignments removing `tags` from `targets` would delete.
+fn count_associations(media_tag: &MediaTag, targets: &[Target], tags: &[String]) -> (usize, usize) {
+    let paths: Vec<PathBuf> = targets
+        .iter()
+        .filter_map(|target| match target {
+            Target::Path(path) => Some(path.clone()),
+            Target::Moved(_) => None,
+        })
+        .collect();
+    let mut medium_ids: Vec<i64> = media_tag
+        .get_media_by_paths(&paths)
+        .unwrap_or_else(|err| print_error_and_exit(err))
+        .into_iter()
+        .flatten()
+        .map(|medium| medium.id)
+        .collect();
+    medium_ids.extend(targets.iter().filter_map(|target| match target {
+        Target::Path(_) => None,
+        Target::Moved(medium) => Some(medium.id),
+    }));
+
+    media_tag
+        .count_associations(&medium_ids, tags)
+        .unwrap_or_else(|err| print_error_and_exit(err))
+}
+
+fn find_db_path() -> Option<PathBuf> {
+    let current_dir = env::current_dir().ok()?;
+    for dir in current_dir.ancestors() {
+        let db_path = dir.join(DB_FILENAME);
+        if db_path.exists() {
+            return Some(db_path);
+        }
+    }
+    None
+}
+
+/// Prints the log messages of the library for `--debug`.
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        eprintln!("debug: {}", record.args());
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+fn main() {
+    let args = Args::parse();
+
+    if args.debug {
+        log::set_logger(&LOGGER).unwrap_or_else(|err| print_error_and_exit(err));
+        log::set_max_level(log::LevelFilter::Debug);
+    }
+
+    if let Commands::Init = args.command {
+        let path = PathBuf::from(DB_FILENAME);
+        if let Err(e) = MediaTag::create(&path) {
+            print_error_and_exit(e);
+        }
+        println!("Initialized empty media-tag database in {}", path.display());
+        return;
+    }
+
+    if let Commands::Search { libraries, .. } = &args.command
+        && !libraries.is_empty()
+    {
+        search_libraries(args);
+        return;
+    }
+
+    let db_path = match find_db_path() {
+        Some(path) => path,
+        None => {
+            eprintln!(
+                "fatal: not a media-tag repository (or any of the parent directories): {DB_FILENAME}"
+            );
+            exit(1);
+        }
+    };
+
+    if let Commands::Migrate = args.command {
+        let media_tag =
+            MediaTag::open_and_migrate(&db_path).unwrap_or_else(|err| print_error_and_exit(err));
+        let version = media_tag
+            .schema_version()
+            .unwrap_or_else(|err| print_error_and_exit(err));
+        println!("Database schema is up to date ({version})");
+        return;
+    }
+
+    let media_tag = open_library(&db_path, &args);
+    let mut out = create_output(&args);
+
+    let color = match args.color {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            args.output.is_none()
+                && io::stdout().is_terminal()
+                && env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        }
+    };
+
+    run(&media_tag, args.command, &mut out, color);
+    release_lock();
+
+    out.finish().unwrap_or_else(|err| print_error_and_exit(err));
+}
+
+/// Opens the library with the database at `db_path` and applies the global options to it.
+fn open_library(db_path: &Path, args: &Args) -> MediaTag {
+    let mut media_tag = MediaTag::open(db_path).unwrap_or_else(|err| {
+        let outdated = matches!(err, media_tag_lib::Error::SchemaMismatch { .. });
+        print_error(err);
+        if outdated {
+            eprintln!("hint: run `mtag migrate` to upgrade the database");
+        }
+        exit(1);
+    });
+    if let Some(timeout) = args.busy_timeout {
+        media_tag
+            .set_busy_timeout(Duration::from_millis(timeout))
+            .unwrap_or_else(|err| print_error_and_exit(err));
+    }
+    media_tag.set_max_retries(args.max_retries);
+    media_tag.set_canonicalize_paths(!args.no_canonicalize);
+    media_tag.set_output_root(args.output_root.clone());
+    media_tag
+}
+
+/// Where listing commands print to, see `--output`.
+fn create_output(args: &Args) -> Output {
+    match &args.output {
+        Some(path) => Output::create(path).unwrap_or_else(|err| {
+            eprintln!("failed to create output file '{}'", path.display());
+            print_error_and_exit(err)
+        }),
+        None => Output::Stdout(io::stdout()),
+    }
+}
+
+/// Runs `search --library` against each of the given libraries. With several libraries, every
+/// file is prefixed with the name of the directory of its library. A tag that one of the
+/// libraries does not have simply matches nothing there.
+fn search_libraries(args: Args) {
+    let mut out = create_output(&args);
+    let Commands::Search {
+        args: ref search,
+        max_results,
+        all,
+        output,
+        ref libraries,
+    } = args.command
+    else {
+        unreachable!("only called for search")
+    };
+    let mut search = search.clone();
+    resolve_tag_files(&mut search);
+
+    let mut results = Vec::new();
+    for library in libraries {
+        let db_path = if library.is_dir() {
+            library.join(DB_FILENAME)
+        } else {
+            library.clone()
+        };
+        if !db_path.is_file() {
+            eprintln!("fatal: not a media-tag library: {}", library.display());
+            exit(1);
+        }
+        let name = db_path
+            .parent()
+            .and_then(|dir| dir.canonicalize().ok())
+            .and_then(|dir| {
+                dir.file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+            })
+            .unwrap_or_else(|| library.display().to_string());
+
+        let media_tag = open_library(&db_path, &args);
+        for medium in search_media(&media_tag, &search) {
+            if let Some(path) = format_medium_path(&media_tag, &medium, output.style()) {
+                results.push((name.clone(), path));
+            }
+        }
+    }
+
+    let limit = if all { usize::MAX } else { max_results };
+    for (name, path) in results.iter().take(limit) {
+        if libraries.len() > 1 && !output.paths_only() {
+            outln!(out, "{name}: {path}");
+        } else {
+            output.print(&mut out, path);
+        }
+    }
+    let remaining = results.len().saturating_sub(limit);
+    if remaining > 0 {
+        eprintln!("... {remaining} more (use --all)");
+    }
+
+    out.finish().unwrap_or_else(|err| print_error_and_exit(err));
+}
+
+/// Whether `command` rewrites large parts of the library, so it must not run concurrently with
+/// another such command.
+fn needs_lock(command: &Commands) -> bool {
+    matches!(
+        command,
+        Commands::DeleteTag { dry_run: false, .. }
+            | Commands::PruneTags { dry_run: false }
+            | Commands::RenameTags { dry_run: false, .. }
+            | Commands::Retag { .. }
+            | Commands::Repair
+            | Commands::UpdateMtimes { .. }
+            | Commands::Relocate { .. }
+            | Commands::Import { .. }
+            | Commands::Apply { dry_run: false, .. }
+    )
+}
+
+/// Colors tag names with the escape sequences of their colors. Without colors, names are printed
+/// as they are.
+struct Palette {
+    colors: HashMap<String, String>,
+}
+
+impl Palette {
+    /// Loads the colors of all tags if `enabled`.
+    fn new(media_tag: &MediaTag, enabled: bool) -> Self {
+        let mut colors = HashMap::new();
+        if enabled {
+            let tags = media_tag
+                .get_tags()
+                .unwrap_or_else(|err| print_error_and_exit(err));
+            for tag in tags {
+                if let Some(code) = tag.color.as_deref().and_then(ansi_color) {
+                    colors.insert(tag.name, code);
+                }
+            }
+        }
+        Self { colors }
+    }
+
+    /// Prints `text` in the color of the tag `tag_name`.
+    fn paint<'a>(&self, tag_name: &str, text: &'a str) -> Cow<'a, str> {
+        match self.colors.get(tag_name) {
+            Some(code) => Cow::Owned(format!("\x1b[{code}m{text}\x1b[0m")),
+            None => Cow::Borrowed(text),
+        }
+    }
+
+    /// Joins the colored `tag_names` with `separator`.
+    fn join(&self, tag_names: &[&str], separator: &str) -> String {
+        tag_names
+            .iter()
+            .map(|name| self.paint(name, name))
+            .collect::<Vec<_>>()
+            .join(separator)
+    }
+}
+
+/// Translates a tag color (a basic color name or `#rrggbb`) into the parameters of an ANSI escape
+/// sequence. Returns `None` for colors that cannot be displayed.
+fn ansi_color(color: &str) -> Option<String> {
+    const NAMES: &[&str] = &[
+        "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    ];
+
+    let color = color.trim();
+    if let Some(hex) = color.strip_prefix('#') {
+        if hex.len() != 6 || !hex.is_ascii() {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        return Some(format!(
+            "38;2;{};{};{}",
+            channel(0)?,
+            channel(2)?,
+            channel(4)?
+        ));
+    }
+    NAMES
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(color))
+        .map(|i| (30 + i).to_string())
+}
+
+fn run(media_tag: &MediaTag, command: Commands, out: &mut Output, color: bool) {
+    if needs_lock(&command) {
+        let lock = media_tag
+            .try_lock()
+            .unwrap_or_else(|err| print_error_and_exit(err));
+        *LIBRARY_LOCK.lock().unwrap_or_else(|e| e.into_inner()) = Some(lock);
+    }
+
+    match command {
+        Commands::Init | Commands::Migrate => unreachable!(),
+        Commands::CreateTag {
+            tags,
+            tag_color,
+            description,
+        } => {
+            for tag in tags {
+                media_tag
+                    .create_tag_full(&tag, tag_color.as_deref(), description.as_deref())
+                    .unwrap_or_else(print_error);
+            }
+        }
+        Commands::ShowTags {
+            with_aliases: true, ..
+        } => {
+            let tags = media_tag
+                .get_tags_with_aliases()
+                .unwrap_or_else(|err| print_error_and_exit(err));
+
+            let palette = Palette::new(media_tag, color);
+            for (tag, aliases) in tags {
+                let name = palette.paint(&tag.name, &tag.name);
+                if aliases.is_empty() {
+                    outln!(out, "{name}");
+                } else {
+                    outln!(out, "{name} ({})", aliases.join(", "));
+                }
+            }
+        }
+        Commands::ShowTags {
+            group_by,
+            prefix,
+            sort,
+            limit,
+            offset,
+            ..
+        } => {
+            let order = match sort {
+                TagSorting::Name => TagOrder::Name,
+                TagSorting::Id => TagOrder::Id,
+                TagSorting::Count => TagOrder::Count,
+            };
+            let tags = media_tag
+                .get_tags_filtered(prefix.as_deref(), order, limit, offset)
+                .unwrap_or_else(|err| print_error_and_exit(err));
+            let palette = Palette::new(media_tag, color);
+
+            match group_by {
+                Some(separator) => {
+                    // Names within a group are paired with the full tag name for its color
+                    let mut groups: BTreeMap<&str, Vec<(&str, &str)>> = BTreeMap::new();
+                    let mut ungrouped = Vec::new();
+                    for tag in &tags {
+                        match tag.name.split_once(separator.as_str()) {
+                            Some((group, name)) => {
+                                groups.entry(group).or_default().push((name, &tag.name));
+                            }
+                            None => ungrouped.push(tag.name.as_str()),
+                        }
+                    }
+
+                    for (group, mut names) in groups {
+                        names.sort_unstable();
+                        outln!(out, "{group}:");
+                        for (name, tag_name) in names {
+                            outln!(out, "  {}", palette.paint(tag_name, name));
+                        }
+                    }
+                    if !ungrouped.is_empty() {
+                        ungrouped.sort_unstable();
+                        outln!(out, "ungrouped:");
+                        for name in ungrouped {
+                            outln!(out, "  {}", palette.paint(name, name));
+                        }
+                    }
+                }
+                None => {
+                    for tag in tags {
+                        outln!(out, "{}", palette.paint(&tag.name, &tag.name));
+                    }
+                }
+            }
+        }
+        Commands::TagCase { case } => match case {
+            Some(case) => {
+                let case = match case {
+                    TagCasing::AsIs => TagCase::AsIs,
+                    TagCasing::Lower => TagCase::Lower,
+                    TagCasing::Kebab => TagCase::Kebab,
+                };
+                media_tag
+                    .set_tag_case(case)
+                    .unwrap_or_else(|err| print_error_and_exit(err));
+            }
+            None => {
+                let case = media_tag
+                    .tag_case()
+                    .unwrap_or_else(|err| print_error_and_exit(err));
+                println!("{case}");
+            }
+        },
+        Commands::Alias { alias, tag } => {
+            media_tag
+                .add_tag_alias(&alias, &tag)
+                .unwrap_or_else(|err| print_error_and_exit(err));
+        }
+        Commands::Search {
+            mut args,
+            max_results,
+            all,
+            output,
+            libraries: _,
+        } => {
+            resolve_tag_files(&mut args);
+            let media = search_media(media_tag, &args);
+
+            let limit = if all { usize::MAX } else { max_results };
+            for medium in media.iter().take(limit) {
+                if let Some(path) = format_medium_path(media_tag, medium, output.style()) {
+                    output.print(out, &path);
+                }
+            }
+            let remaining = media.len().saturating_sub(limit);
+            if remaining > 0 {
+                eprintln!("... {remaining} more (use --all)");
+            }
+        }
+        Commands::SaveQuery {
+            name,
+            queries,
+            exclude,
+            any,
+        } => {
+            let query = SearchQuery {
+                include: queries,
+                exclude,
+                any,
+            };
+            media_tag
+                .save_query(&name, &query)
+                .unwrap_or_else(|err| print_error_and_exit(err));
+        }
+        Commands::RunQuery { name, path_style } => {
+            let media = media_tag
+                .run_saved_query(&name)
+                .unwrap_or_else(|err| print_error_and_exit(err));
+
+            for medium in &media {
+                if let Some(path) = format_medium_path(media_tag, medium, path_style) {
+                    outln!(out, "{path}");
+                }
+            }
+        }
+        Commands::ListQueries => {
+            let queries = media_tag
+                .saved_queries()
+                .unwrap_or_else(|err| print_error_and_exit(err));
+
+            for (name, query) in queries {
+                let mut line = format!("{name}: {}", query.include.join(" "));
+                if !query.exclude.is_empty() {
+                    line.push_str(&format!(" --not {}", query.exclude.join(" ")));
+                }
+                if query.any {
+                    line.push_str(" --any");
+                }
+                outln!(out, "{line}");
+            }
+        }
+        Commands::DeleteQuery { name } => {
+            media_tag
+                .delete_saved_query(&name)
+                .unwrap_or_else(|err| print_error_and_exit(err));
+        }
+        Commands::Status {
+            include_untracked,
+            verbose,
+            exclude_untagged,
+            only_untagged,
+            count,
+            show_rating,
+            tree,
+            tag_sep,
+            output,
+        } => {
+            let media_tag_data = media_tag
+                .load_media_tag()
+                .unwrap_or_else(|err| print_error_and_exit(err));
+
+            let media = media_tag_data.media.iter().filter(|media| {
+                if exclude_untagged {
+                    !media.tags.is_empty()
+                } else if only_untagged {
+                    media.tags.is_empty()
+                } else {
+                    true
+                }
+            });
+            let palette = Palette::new(media_tag, color);
+            if tree {
+                print_tree(out, &media_tag_data, &palette, media, &tag_sep, verbose);
+            } else {
+                for media in media {
+                    let Some(path) = format_medium_path(media_tag, media, output.style()) else {
+                        continue;
+                    };
+                    if output.paths_only() {
+                        output.print(out, &path);
+                        continue;
+                    }
+                    let tag_names = media_tag_data.names(media);
+
+                    if show_rating {
+                        let rating = media.rating.map_or("-".to_string(), |r| r.to_string());
+                        write!(out, "{rating}  ").unwrap_or_else(|err| print_error_and_exit(err));
+                    }
+                    if count {
+                        write!(out, "{:>4}  ", media.tags.len())
+                            .unwrap_or_else(|err| print_error_and_exit(err));
+                    }
+                    outln!(out, "{path} - {}", palette.join(&tag_names, &tag_sep));
+                    if let Some(note) = media.note.as_ref().filter(|_| verbose) {
+                        outln!(out, "    note: {note}");
+                    }
+                }
+            }
+
+            if include_untracked {
+                let untracked = media_tag
+                    .untracked(&WalkOptions::default())
+                    .unwrap_or_else(|err| print_error_and_exit(err));
+
+                for path in untracked {
+                    if let Some(path) = format_path(media_tag, &path, output.style()) {
+                        if output.paths_only() {
+                            output.print(out, &path);
+                        } else {
+                            outln!(out, "? {path}");
+                        }
+                    }
+                }
+            }
+        }
+        Commands::Track { files } => {
+            for file in files {
+                if let Err(err) = media_tag.track(&file) {
+                    eprintln!("failed to track '{}'", file.display());
+                    print_error(err);
+                }
+            }
+        }
+        Commands::Forget { files } => {
+            for file in files {
+                if let Err(err) = media_tag.forget_medium(&file) {
+                    eprintln!("failed to forget '{}'", file.display());
+                    print_error(err);
+                }
+            }
+        }
+        Commands::Add {
+            args,
+            create,
+            no_create: _,
+        } => {
+            let missing = if create {
+                MissingTag::Create
+            } else {
+                MissingTag::Error
+            };
+            let quiet = args.quiet;
+            let (targets, tags) = parse_args(media_tag, args);
+            if missing == MissingTag::Error {
+                let names: Vec<&str> = tags
+                    .iter()
+                    .filter(|tag| parse_tag_id(tag).is_none())
+                    .map(String::as_str)
+                    .collect();
+                let unknown = media_tag
+                    .missing_tags(&names)
+                    .unwrap_or_else(|err| print_error_and_exit(err));
+                if !unknown.is_empty() {
+                    for tag in &unknown {
+                        eprintln!("error: tag \"{tag}\" does not exist");
+                    }
+                    eprintln!("hint: pass --create to create missing tags");
+                    exit(1);
+                }
+            }
+            let (mut present, mut errors) = (0, 0);
+            for target in &targets {
+                for tag in &tags {
+                    let result = match (target, parse_tag_id(tag)) {
+                        (Target::Path(path), Some(id)) => media_tag.add_tag_id_by_medium(path, id),
+                        (Target::Path(path), None) => media_tag.add_tag_with(path, tag, missing),
+                        (Target::Moved(medium), Some(id)) => {
+                            media_tag.add_tag_id_to_medium(medium.id, id)
+                        }
+                        (Target::Moved(medium), None) => {
+                            media_tag.add_tag_to_medium_with(medium.id, tag, missing)
+                        }
+                    };
+                    match result {
+                        Ok(true) => {}
+                        Ok(false) => present += 1,
+                        Err(err) => {
+                            eprintln!(
+                                "failed to add tag '{}' to '{}'",
+                                tag,
+                                target.path().display()
+                            );
+                            print_error(err);
+                            errors += 1;
+                        }
+                    }
+                }
+            }
+
+            if !quiet {
+                println!(
+                    "Tagged {} files with {} tags ({present} already present, {errors} errors)",
+                    targets.len(),
+                    tags.len()
+                );
+            }
+        }
+        Commands::Remove { args, confirm, yes } => {
+            let quiet = args.quiet;
+            let files_from_stdin = args.stdin || args.stdin0;
+            let (targets, tags) = parse_args(media_tag, args);
+            let print_summary = |missing: usize, errors: usize| {
+                if !quiet {
+                    println!(
+                        "Removed {} tags from {} files ({missing} not present, {errors} errors)",
+                        tags.len(),
+                        targets.len()
+                    );
+                }
+            };
 
-        /// The tags you are looking for
-        #[arg(num_args = 1..)]
-        queries: Vec<String>,
+            if !yes && (confirm || targets.len() * tags.len() >= BULK_THRESHOLD) {
+                if files_from_stdin {
+                    eprintln!("error: cannot ask for confirmation while reading files from stdin");
+                    eprintln!("hint: pass --yes to remove the tags without asking");
+                    exit(1);
+                }
+                let (associations, files) = count_associations(media_tag, &targets, &tags);
+                print!("This will remove {associations} tags from {files} files. Continue? [y/N] ");
+                io::stdout()
+                    .flush()
+                    .unwrap_or_else(|err| print_error_and_exit(err));
 
-        /// The tags you want to exclude
-        #[arg(long = "not", num_args = 1..)]
-        exclude: Vec<String>,
-    },
-    /// Get a list of all tagged files along with their tags
-    Status,
-    /// Tag one or more files with one or more tags
-    Add { parameters: Vec<String> },
-    /// Remove one or more tags from one or more files
-    Remove { parameters: Vec<String> },
-}
+                let mut answer = String::new();
+                io::stdin()
+                    .read_line(&mut answer)
+                    .unwrap_or_else(|err| print_error_and_exit(err));
+                if !matches!(answer.trim().to_lowercase().as_str(), "y" | "yes") {
+                    println!("Aborted");
+                    return;
+                }
+            }
 
-const DB_FILENAME: &str = ".media_tag.db";
+            if targets.len() * tags.len() >= BULK_THRESHOLD {
+                let paths: Option<Vec<PathBuf>> = targets
+                    .iter()
+                    .map(|target| match target {
+                        Target::Path(path) => Some(path.clone()),
+                        Target::Moved(_) => None,
+                    })
+                    .collect();
+                if let Some(paths) = paths {
+                    let removed = media_tag
+                        .remove_tags_bulk(&paths, &tags, true)
+                        .unwrap_or_else(|err| print_error_and_exit(err));
+                    print_summary(paths.len() * tags.len() - removed, 0);
+                    return;
+                }
+            }
 
-fn parse_args(parameters: Vec<String>) -> (Vec<PathBuf>, Vec<String>) {
-    let mut paths = Vec::new();
-    let mut tags = Vec::new();
+            let (mut missing, mut errors) = (0, 0);
+            for target in &targets {
+                for tag in &tags {
+                    let result = match target {
+                        Target::Path(path) => media_tag.remove_tag(path, tag),
+                        Target::Moved(medium) => media_tag.remove_tag_from_medium(medium.id, tag),
+                    };
+                    match result {
+                        Ok(true) => {}
+                        Ok(false) => missing += 1,
+                        Err(err) => {
+                            eprintln!(
+                                "failed to remove tag '{}' from '{}'",
+                                tag,
+                                target.path().display()
+                            );
+                            print_error(err);
+                            errors += 1;
+                        }
+                    }
+                }
+            }
+            print_summary(missing, errors);
+        }
+        Commands::Retag {
+            add,
+            remove,
+            create,
+            queries,
+            exclude,
+            any,
+        } => {
+            let query = SearchQuery {
+                include: queries,
+                exclude,
+                any,
+            };
+            // Adding first lets `--add reviewed --remove needs-review --query needs-review` work
+            if !add.is_empty() {
+                let missing = if create {
+                    MissingTag::Create
+                } else {
+                    MissingTag::Error
+                };
+                let add: Vec<&str> = add.iter().map(String::as_str).collect();
+                let added = media_tag
+                    .add_tags_matching(&query, &add, missing)
+                    .unwrap_or_else(|err| print_error_and_exit(err));
+                println!("Added {added} tags");
+            }
+            if !remove.is_empty() {
+                let remove: Vec<&str> = remove.iter().map(String::as_str).collect();
+                let removed = media_tag
+                    .remove_tags_matching(&query, &remove)
+                    .unwrap_or_else(|err| print_error_and_exit(err));
+                println!("Removed {removed} tags");
+            }
+        }
+        Commands::CopyTags { src, dst } => {
+            let added = media_tag
+                .copy_tags(&src, &dst)
+                .unwrap_or_else(|err| print_error_and_exit(err));
 
-    for parameter in parameters {
-        let path = PathBuf::from(&parameter);
-        if path.exists() {
-            paths.push(path);
-        } else {
-            tags.push(parameter);
+            println!("Added {added} tags to {}", dst.display());
         }
-    }
-    (paths, tags)
-}
+        Commands::ExportSidecars => {
+            let written = media_tag
+                .export_sidecars(|path, err| {
+                    eprintln!("failed to write sidecar '{}'", path.display());
+                    print_error(err);
+                })
+                .unwrap_or_else(|err| print_error_and_exit(err));
 
-fn find_db_path() -> Option<PathBuf> {
-    let current_dir = env::current_dir().ok()?;
-    for dir in current_dir.ancestors() {
-        let db_path = dir.join(DB_FILENAME);
-        if db_path.exists() {
-            return Some(db_path);
+            println!("Wrote {written} sidecar files");
         }
-    }
-    None
-}
+        Commands::TagInteractive { dir } => {
+            let media_tag_data = media_tag
+                .load_media_tag()
+                .unwrap_or_else(|err| print_error_and_exit(err));
+            let tagged: HashSet<&Path> = media_tag_data
+                .media
+                .iter()
+                .filter(|medium| !medium.tags.is_empty())
+                .map(|medium| medium.relative.as_path())
+                .collect();
+            let files = media_tag
+                .walk_files(&dir, &WalkOptions::default())
+                .unwrap_or_else(|err| print_error_and_exit(err));
 
-fn main() {
-    let args = Args::parse();
+            println!("Enter space separated tags for each file (empty line skips, q quits)");
+            let mut lines = io::stdin().lock().lines();
+            let is_tagged = |file: &Path| {
+                media_tag
+                    .stored_path(file)
+                    .is_ok_and(|path| tagged.contains(Path::new(&path)))
+            };
+            for file in files.iter().filter(|file| !is_tagged(file)) {
+                print!("{}\ntags> ", file.display());
+                io::stdout()
+                    .flush()
+                    .unwrap_or_else(|err| print_error_and_exit(err));
 
-    if let Commands::Init = args.command {
-        let path = PathBuf::from(DB_FILENAME);
-        if path.exists() {
-            eprintln!("'{DB_FILENAME}' already exists in this directory.");
-            return;
+                let line = match lines.next() {
+                    Some(line) => line.unwrap_or_else(|err| print_error_and_exit(err)),
+                    None => break,
+                };
+                let line = line.trim();
+                if line == "q" {
+                    break;
+                }
+                for tag in line.split_whitespace() {
+                    if let Err(err) = media_tag.add_tag_creating(file, tag) {
+                        eprintln!("failed to add tag '{}' to '{}'", tag, file.display());
+                        print_error(err);
+                    }
+                }
+            }
         }
-        if let Err(e) = MediaTag::new(DB_FILENAME) {
-            print_error_and_exit(e);
+        Commands::LintTags { threshold } => {
+            let groups = media_tag
+                .find_similar_tags(threshold)
+                .unwrap_or_else(|err| print_error_and_exit(err));
+
+            for group in groups {
+                println!("possible duplicates: {}", group.join(", "));
+            }
         }
-        println!("Initialized empty media-tag database in {}", path.display());
-        return;
-    }
+        Commands::DeleteTag { name, dry_run } => {
+            let plan = media_tag
+                .delete_tag_plan(&name)
+                .unwrap_or_else(|err| print_error_and_exit(err));
+            let tag = &plan.deleted_tags[0];
+            if dry_run {
+                println!(
+                    "Would delete tag \"{tag}\" and remove it from {} files",
+                    plan.removed_associations
+                );
+                return;
+            }
 
-    let db_path = match find_db_path() {
-        Some(path) => path,
-        None => {
-            eprintln!(
-                "fatal: not a media-tag repository (or any of the parent directories): {DB_FILENAME}"
+            media_tag
+                .delete_tag(&name)
+                .unwrap_or_else(|err| print_error_and_exit(err));
+            println!(
+                "Deleted tag \"{tag}\" and removed it from {} files",
+                plan.removed_associations
             );
-            exit(1);
         }
-    };
+        Commands::PruneTags { dry_run } => {
+            let tags = if dry_run {
+                media_tag.unused_tags()
+            } else {
+                media_tag.prune_tags()
+            }
+            .unwrap_or_else(|err| print_error_and_exit(err));
+
+            for tag in &tags {
+                println!("{}", tag);
+            }
+            if dry_run {
+                println!("Would remove {} unused tags", tags.len());
+            } else {
+                println!("Removed {} unused tags", tags.len());
+            }
+        }
+        Commands::RenameTags {
+            pattern,
+            replacement,
+            dry_run,
+        } => {
+            let renames = if dry_run {
+                media_tag.tag_renames_by_pattern(&pattern, &replacement)
+            } else {
+                media_tag.rename_tags_by_pattern(&pattern, &replacement)
+            }
+            .unwrap_or_else(|err| print_error_and_exit(err));
+
+            for (old, new) in &renames {
+                println!("{} -> {}", old, new);
+            }
+            if dry_run {
+                println!("Would rename {} tags", renames.len());
+            } else {
+                println!("Renamed {} tags", renames.len());
+            }
+        }
+        Commands::Duplicates {
+            algo,
+            rehash,
+            threads,
+        } => {
+            media_tag
+                .hash_media_threaded(algo, rehash, threads, |path, err| {
+                    eprintln!("failed to hash '{}'", path.display());
+                    print_error(err);
+                })
+                .unwrap_or_else(|err| print_error_and_exit(err));
+            let groups = media_tag
+                .find_duplicates()
+                .unwrap_or_else(|err| print_error_and_exit(err));
 
-    let media_tag = MediaTag::new(&db_path).unwrap_or_else(|err| print_error_and_exit(err));
+            for (i, group) in groups.iter().enumerate() {
+                if i > 0 {
+                    outln!(out);
+                }
+                for path in group {
+                    outln!(out, "{}", path.display());
+                }
+            }
+        }
+        Commands::Repair => {
+            let removed = media_tag
+                .dedupe_associations()
+                .unwrap_or_else(|err| print_error_and_exit(err));
 
-    match args.command {
-        Commands::Init => unreachable!(),
-        Commands::CreateTag { tags } => {
-            for tag in tags {
-                media_tag.create_tag(&tag).unwrap_or_else(print_error);
+            println!("Removed {removed} duplicate tag assignments");
+        }
+        Commands::UpdateMtimes { all } => {
+            let updated = media_tag
+                .update_mtimes(all, |path, err| {
+                    eprintln!(
+                        "failed to read the modification time of '{}'",
+                        path.display()
+                    );
+                    print_error(err);
+                })
+                .unwrap_or_else(|err| print_error_and_exit(err));
+
+            println!("Recorded the modification times of {updated} files");
+        }
+        Commands::Untracked {
+            depth,
+            follow_symlinks,
+        } => {
+            let options = WalkOptions {
+                max_depth: depth,
+                follow_symlinks,
+            };
+            let untracked = media_tag
+                .untracked(&options)
+                .unwrap_or_else(|err| print_error_and_exit(err));
+
+            for path in untracked {
+                outln!(out, "{}", path.display());
+            }
+        }
+        Commands::Top {
+            least,
+            most: _,
+            limit,
+        } => {
+            let media = media_tag
+                .media_by_tag_count(least, limit)
+                .unwrap_or_else(|err| print_error_and_exit(err));
+
+            for (medium, count) in media {
+                outln!(out, "{count:>4}  {}", medium.path.display());
+            }
+        }
+        Commands::Similar { file, min_shared } => {
+            let similar = media_tag
+                .similar_media(&file, min_shared)
+                .unwrap_or_else(|err| print_error_and_exit(err));
+
+            for (medium, shared) in similar {
+                outln!(out, "{shared:>4}  {}", medium.path.display());
             }
         }
-        Commands::ShowTags => {
+        Commands::Stats => {
             let tags = media_tag
                 .get_tags()
                 .unwrap_or_else(|err| print_error_and_exit(err));
+            let media = media_tag
+                .media_count()
+                .unwrap_or_else(|err| print_error_and_exit(err));
+            let tagged = media_tag
+                .tagged_media_count()
+                .unwrap_or_else(|err| print_error_and_exit(err));
 
-            for tag in tags {
-                println!("{}", tag.name);
+            println!("tags:           {}", tags.len());
+            println!("media:          {media}");
+            println!("tagged media:   {tagged}");
+            println!("untagged media: {}", media - tagged);
+        }
+        Commands::TagInfo { name } => {
+            let detail = media_tag
+                .tag_detail(&name)
+                .unwrap_or_else(|err| print_error_and_exit(err));
+            let used = |timestamp: Option<i64>| match timestamp {
+                Some(timestamp) => format_timestamp(timestamp),
+                None if detail.files == 0 => "never".to_string(),
+                None => "unknown".to_string(),
+            };
+
+            outln!(out, "name:        {}", detail.tag.name);
+            if let Some(description) = &detail.tag.description {
+                outln!(out, "description: {description}");
+            }
+            if let Some(color) = &detail.tag.color {
+                outln!(out, "color:       {color}");
+            }
+            outln!(out, "files:       {}", detail.files);
+            outln!(out, "first used:  {}", used(detail.first_used));
+            outln!(out, "last used:   {}", used(detail.last_used));
+            if !detail.sample.is_empty() {
+                outln!(out, "recently tagged:");
+                for path in &detail.sample {
+                    outln!(out, "  {}", path.display());
+                }
             }
         }
-        Commands::Search {
-            any,
-            queries,
-            exclude,
-        } => {
-            let media_tag_data = media_tag
-                .load_media_tag()
+        Commands::Distribution => {
+            let counts = media_tag
+                .get_tag_counts()
                 .unwrap_or_else(|err| print_error_and_exit(err));
+            let name_width = counts.iter().map(|(name, _)| name.chars().count()).max();
+            let Some(name_width) = name_width else {
+                return;
+            };
+            let max = counts[0].1;
+            let count_width = max.to_string().len();
+            let bar_width = terminal_width()
+                .saturating_sub(name_width + count_width + 2)
+                .max(10);
 
-            media_tag_data
-                .media
-                .iter()
-                .filter(|medium| {
-                    let has_tag = |query_tag: &String| {
-                        medium.tags.iter().any(|&tag_id| {
-                            media_tag_data
-                                .tags
-                                .get(&tag_id)
-                                .map_or(false, |name| name == query_tag)
-                        })
-                    };
+            for (name, count) in &counts {
+                let bar = if max == 0 {
+                    0
+                } else {
+                    (*count as usize * bar_width).div_ceil(max as usize)
+                };
+                outln!(
+                    out,
+                    "{name:<name_width$} {count:>count_width$} {}",
+                    "#".repeat(bar)
+                );
+            }
+        }
+        Commands::Combinations { min } => {
+            let distribution = media_tag
+                .tag_set_distribution()
+                .unwrap_or_else(|err| print_error_and_exit(err));
 
-                    let matches_positive = if any {
-                        if queries.is_empty() {
-                            true
-                        } else {
-                            queries.iter().any(has_tag)
-                        }
-                    } else {
-                        queries.iter().all(has_tag)
-                    };
+            let palette = Palette::new(media_tag, color);
+            for (names, count) in distribution.iter().filter(|(_, count)| *count >= min) {
+                let names: Vec<&str> = names.iter().map(String::as_str).collect();
+                let files = if *count == 1 { "file" } else { "files" };
+                if names.is_empty() {
+                    outln!(out, "(untagged): {count} {files}");
+                } else {
+                    outln!(out, "[{}]: {count} {files}", palette.join(&names, ", "));
+                }
+            }
+        }
+        Commands::CompleteTags => {
+            let counts = media_tag
+                .get_tag_counts()
+                .unwrap_or_else(|err| print_error_and_exit(err));
 
-                    let matches_negative = exclude.iter().any(has_tag);
+            for (name, _) in counts {
+                outln!(out, "{name}");
+            }
+        }
+        Commands::Note { file, text } => match text {
+            Some(text) => {
+                let note = Some(text.as_str()).filter(|text| !text.is_empty());
+                media_tag
+                    .set_note(&file, note)
+                    .unwrap_or_else(|err| print_error_and_exit(err));
+            }
+            None => {
+                let note = media_tag
+                    .get_note(&file)
+                    .unwrap_or_else(|err| print_error_and_exit(err));
+                if let Some(note) = note {
+                    println!("{note}");
+                }
+            }
+        },
+        Commands::Rate { file, rating } => {
+            media_tag
+                .set_rating(&file, Some(rating))
+                .unwrap_or_else(|err| print_error_and_exit(err));
+        }
+        Commands::Graph { min } => {
+            let dot = media_tag
+                .export_cooccurrence_dot(min)
+                .unwrap_or_else(|err| print_error_and_exit(err));
 
-                    matches_positive && !matches_negative
-                })
-                .for_each(|medium| println!("{}", medium.path.display()));
+            write!(out, "{dot}").unwrap_or_else(|err| print_error_and_exit(err));
         }
-        Commands::Status => {
-            let media_tag_data = media_tag
-                .load_media_tag()
+        Commands::Show { file, json } => {
+            let medium = media_tag
+                .get_medium_by_path(&file)
                 .unwrap_or_else(|err| print_error_and_exit(err));
+            let data = MediaTags {
+                tags: media_tag
+                    .get_tag_id_map()
+                    .unwrap_or_else(|err| print_error_and_exit(err)),
+                media: Vec::new(),
+            };
+            let tags = data.names(&medium);
 
-            for media in &media_tag_data.media {
-                let tag_names: Vec<&str> = media
-                    .tags
-                    .iter()
-                    .filter_map(|id| media_tag_data.tags.get(id).map(|s| s.as_str()))
-                    .collect();
+            if json {
+                let record = serde_json::json!({
+                    "id": medium.id,
+                    "path": medium.path.display().to_string(),
+                    "tags": tags,
+                    "note": medium.note,
+                    "rating": medium.rating,
+                    "mtime": medium.mtime,
+                });
+                outln!(out, "{record}");
+            } else {
+                outln!(out, "id:    {}", medium.id);
+                outln!(out, "path:  {}", medium.path.display());
+                outln!(out, "tags:  {}", tags.join(", "));
+                if let Some(note) = &medium.note {
+                    outln!(out, "note:  {note}");
+                }
+                if let Some(rating) = medium.rating {
+                    outln!(out, "rating: {rating}");
+                }
+                if let Some(mtime) = medium.mtime {
+                    outln!(out, "modified: {}", format_timestamp(mtime));
+                }
+            }
+        }
+        Commands::History { file } => {
+            let history = media_tag
+                .medium_tag_history(&file)
+                .unwrap_or_else(|err| print_error_and_exit(err));
 
-                println!("{} - {}", media.path.display(), tag_names.join(","));
+            for (tag, added) in history {
+                outln!(out, "{}  {tag}", format_timestamp(added));
             }
         }
-        Commands::Add { parameters } => {
-            let (paths, tags) = parse_args(parameters);
-            for path in &paths {
-                for tag in &tags {
-                    media_tag.add_tag(path, tag).unwrap_or_else(|err| {
-                        eprintln!("failed to add tag '{}' to '{}'", tag, path.display());
-                        print_error(err);
-                    });
+        Commands::VerifyCase => {
+            let mismatches = media_tag
+                .verify_path_case()
+                .unwrap_or_else(|err| print_error_and_exit(err));
+
+            for (stored, actual) in mismatches {
+                outln!(out, "{stored} -> {actual}");
+            }
+        }
+        Commands::Rename { old, new } => {
+            if new.exists() {
+                eprintln!("error: '{}' already exists", new.display());
+                exit(1);
+            }
+            fs::rename(&old, &new).unwrap_or_else(|err| print_error_and_exit(err));
+
+            if let Err(err) = media_tag.move_medium(&old, &new) {
+                print_error(err);
+                if let Err(err) = fs::rename(&new, &old) {
+                    eprintln!(
+                        "failed to move '{}' back to '{}'",
+                        new.display(),
+                        old.display()
+                    );
+                    print_error(err);
                 }
+                exit(1);
             }
         }
-        Commands::Remove { parameters } => {
-            let (paths, tags) = parse_args(parameters);
-            for path in &paths {
-                for tag in &tags {
-                    media_tag.remove_tag(path, tag).unwrap_or_else(|err| {
-                        eprintln!("failed to remove tag '{}' from '{}'", tag, path.display());
-                        print_error(err);
-                    });
+        Commands::Relocate { old, new } => {
+            let moved = media_tag
+                .relocate_prefix(&old, &new)
+                .unwrap_or_else(|err| print_error_and_exit(err));
+
+            println!("Relocated {moved} files");
+        }
+        Commands::Import {
+            file,
+            format,
+            on_conflict,
+            batch_size,
+            strict,
+        } => {
+            let policy = match on_conflict {
+                OnConflict::Skip => ConflictPolicy::Skip,
+                OnConflict::Error => ConflictPolicy::Error,
+                OnConflict::Replace => ConflictPolicy::Replace,
+            };
+            let format =
+                format.unwrap_or_else(|| match file.extension().and_then(|ext| ext.to_str()) {
+                    Some("toml") => ExportFormat::Toml,
+                    Some("ndjson" | "jsonl") => ExportFormat::Ndjson,
+                    _ => ExportFormat::Csv,
+                });
+            let added = match format {
+                ExportFormat::Csv => {
+                    let reader = File::open(&file).unwrap_or_else(|err| print_error_and_exit(err));
+                    media_tag.import_csv_batched(reader, policy, batch_size)
+                }
+                ExportFormat::Toml => {
+                    let contents =
+                        fs::read_to_string(&file).unwrap_or_else(|err| print_error_and_exit(err));
+                    media_tag.import_toml(&contents, policy)
                 }
+                ExportFormat::Ndjson => {
+                    let reader = File::open(&file).unwrap_or_else(|err| print_error_and_exit(err));
+                    let summary = media_tag
+                        .import_ndjson(reader, policy, batch_size, strict, |line, err| {
+                            eprintln!("skipping line {line}");
+                            print_error(err);
+                        })
+                        .unwrap_or_else(|err| print_error_and_exit(err));
+                    println!(
+                        "Imported {} tags on {} files, skipped {} malformed lines",
+                        summary.added, summary.files, summary.skipped
+                    );
+                    return;
+                }
+            }
+            .unwrap_or_else(|err| print_error_and_exit(err));
+
+            println!("Imported {added} tags");
+        }
+        Commands::Apply { spec, dry_run } => {
+            let contents =
+                fs::read_to_string(&spec).unwrap_or_else(|err| print_error_and_exit(err));
+            let plan = if dry_run {
+                media_tag.spec_changes(&contents)
+            } else {
+                media_tag.apply_spec(&contents)
+            }
+            .unwrap_or_else(|err| print_error_and_exit(err));
+
+            for tag in &plan.created_tags {
+                println!("new tag {tag}");
+            }
+            for change in &plan.changes {
+                let added = change.added.iter().map(|tag| format!("+{tag}"));
+                let removed = change.removed.iter().map(|tag| format!("-{tag}"));
+                println!(
+                    "{}: {}",
+                    change.path,
+                    added.chain(removed).collect::<Vec<_>>().join(" ")
+                );
             }
+            if dry_run {
+                println!(
+                    "Would change the tags of {} files and create {} tags",
+                    plan.changes.len(),
+                    plan.created_tags.len()
+                );
+            } else {
+                println!(
+                    "Changed the tags of {} files and created {} tags",
+                    plan.changes.len(),
+                    plan.created_tags.len()
+                );
+            }
+        }
+        Commands::Export { format, batch_size } => match format {
+            ExportFormat::Csv => media_tag
+                .write_csv(&mut *out, batch_size)
+                .unwrap_or_else(|err| print_error_and_exit(err)),
+            ExportFormat::Ndjson => media_tag
+                .write_ndjson(&mut *out, batch_size)
+                .unwrap_or_else(|err| print_error_and_exit(err)),
+            ExportFormat::Toml => {
+                let exported = media_tag
+                    .export_toml()
+                    .unwrap_or_else(|err| print_error_and_exit(err));
+                write!(out, "{exported}").unwrap_or_else(|err| print_error_and_exit(err));
+            }
+        },
+    }
+}
+
+/// Prints `media` grouped by directory, each directory followed by its files and subdirectories
+/// indented by one more level.
+fn print_tree<'a>(
+    out: &mut Output,
+    media_tag_data: &MediaTags,
+    palette: &Palette,
+    media: impl Iterator<Item = &'a Medium>,
+    tag_sep: &str,
+    verbose: bool,
+) {
+    let mut entries: Vec<&Medium> = media.collect();
+    entries.sort_unstable_by(|a, b| a.relative.cmp(&b.relative));
+
+    let mut previous: Vec<Cow<str>> = Vec::new();
+    for medium in entries {
+        let mut components: Vec<Cow<str>> = medium
+            .relative
+            .iter()
+            .map(|component| component.to_string_lossy())
+            .collect();
+        let name = components.pop().unwrap_or_default();
+        let common = previous
+            .iter()
+            .zip(&components)
+            .take_while(|(a, b)| a == b)
+            .count();
+        for (depth, dir) in components.iter().enumerate().skip(common) {
+            outln!(out, "{}{dir}/", "  ".repeat(depth));
+        }
+
+        let indent = "  ".repeat(components.len());
+        let tag_names = media_tag_data.names(medium);
+        outln!(
+            out,
+            "{indent}{name} - {}",
+            palette.join(&tag_names, tag_sep)
+        );
+        if let Some(note) = medium.note.as_ref().filter(|_| verbose) {
+            outln!(out, "{indent}    note: {note}");
         }
+        previous = components;
+    }
+}
+
+/// Returns the width of the terminal from the `COLUMNS` environment variable, or 80 if it is not
+/// set.
+fn terminal_width() -> usize {
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(80)
+}
+
+/// Formats the absolute `path` of a file inside the library according to `style`. Returns `None`
+/// if a canonical path is requested for a file that does not exist.
+fn format_path(media_tag: &MediaTag, path: &Path, style: PathStyle) -> Option<String> {
+    match style {
+        PathStyle::Stored => Some(
+            media_tag
+                .stored_path(path)
+                .unwrap_or_else(|err| print_error_and_exit(err)),
+        ),
+        PathStyle::Absolute => Some(path.display().to_string()),
+        PathStyle::Canonical => path
+            .canonicalize()
+            .ok()
+            .map(|path| path.display().to_string()),
+    }
+}
+
+/// Formats the path of a tracked file, taking the stored path from the medium itself since
+/// [`Medium::path`] is not below the library root with `--output-root`.
+fn format_medium_path(media_tag: &MediaTag, medium: &Medium, style: PathStyle) -> Option<String> {
+    match style {
+        PathStyle::Stored => Some(medium.relative.to_string_lossy().into_owned()),
+        _ => format_path(media_tag, &medium.path, style),
+    }
+}
+
+/// Formats a unix timestamp as `YYYY-MM-DD HH:MM:SS` (UTC). `0` is treated as unknown.
+fn format_timestamp(timestamp: i64) -> String {
+    if timestamp == 0 {
+        return "unknown".to_string();
     }
+
+    let (days, seconds) = (timestamp.div_euclid(86400), timestamp.rem_euclid(86400));
+
+    // Civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// Days since the epoch of a date in the proleptic Gregorian calendar, the inverse of the
+/// conversion in [`format_timestamp`].
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
 }
 
 fn print_error(e: impl std::error::Error) {
@@ -204,3 +2493,50 @@ fn print_error_and_exit(e: impl std::error::Error) -> ! {
     print_error(e);
     exit(1);
 }
+
+/// Releases the library lock if the command holds it.
+fn release_lock() {
+    LIBRARY_LOCK
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take();
+}
+
+/// Deletes the temporary file of an unfinished `--output` file.
+fn discard_output() {
+    let temp_path = OUTPUT_TEMP_PATH
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .take();
+    if let Some(temp_path) = temp_path {
+        let _ = fs::remove_file(temp_path);
+    }
+}
+
+/// Like `process::exit`, but releases the library lock and discards unfinished output first.
+fn exit(code: i32) -> ! {
+    release_lock();
+    discard_output();
+    process::exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn args_are_consistent() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn paths_are_recognized_by_separator_or_media_extension() {
+        for path in ["photo.jpg", "Photo.JPG", "dir/name", "song.flac"] {
+            assert!(looks_like_path(path), "{path}");
+        }
+        for tag in ["beach", "v1.0", "summer.vacation", "#12"] {
+            assert!(!looks_like_path(tag), "{tag}");
+        }
+    }
+}
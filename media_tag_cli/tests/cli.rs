@@ -0,0 +1,200 @@
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A library in a fresh temporary directory that is deleted on drop.
+struct TempLibrary {
+    dir: PathBuf,
+}
+
+impl TempLibrary {
+    fn new() -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "mtag-cli-test-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let library = Self {
+            dir: dir.canonicalize().unwrap(),
+        };
+        library.success(&["init"]);
+        library
+    }
+
+    /// Runs `mtag` with `args` in the library directory.
+    fn run(&self, args: &[&str]) -> Output {
+        self.run_with_stdin(args, b"")
+    }
+
+    /// Runs `mtag` with `args` in the library directory, feeding it `stdin`.
+    fn run_with_stdin(&self, args: &[&str], stdin: &[u8]) -> Output {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_mtag"))
+            .args(args)
+            .current_dir(&self.dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(stdin).unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    /// Runs `mtag` with `args`, asserts that it succeeded and returns its stdout.
+    fn success(&self, args: &[&str]) -> String {
+        self.success_with_stdin(args, b"")
+    }
+
+    /// Like [`TempLibrary::success`], feeding the command `stdin`.
+    fn success_with_stdin(&self, args: &[&str], stdin: &[u8]) -> String {
+        let output = self.run_with_stdin(args, stdin);
+        assert!(
+            output.status.success(),
+            "mtag {args:?} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    /// Creates empty files at the given paths relative to the library directory.
+    fn files(&self, rel_paths: &[&str]) {
+        for rel_path in rel_paths {
+            let path = self.path(rel_path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+    }
+
+    /// The files tagged with `tag`, relative to the library directory.
+    fn tagged(&self, tag: &str) -> Vec<String> {
+        let output = self.success(&["search", tag, "--paths", "--relative", "--print0"]);
+        output.split_terminator('\0').map(str::to_string).collect()
+    }
+
+    /// Names of all tags in the order they were created.
+    fn tags(&self) -> Vec<String> {
+        self.success(&["show-tags"])
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Names of the entries of the library directory, sorted.
+    fn entries(&self) -> Vec<String> {
+        let mut entries: Vec<String> = fs::read_dir(&self.dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    /// Joins `rel_path` onto the library directory.
+    fn path(&self, rel_path: &str) -> PathBuf {
+        self.dir.join(rel_path)
+    }
+}
+
+impl Drop for TempLibrary {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn failed_command_leaves_no_output_file() {
+    let library = TempLibrary::new();
+
+    let output = library.run(&["--output", "out.txt", "show", "missing.jpg"]);
+    assert!(!output.status.success());
+    assert_eq!(library.entries(), [".media_tag.db"]);
+
+    library.success(&["create-tag", "beach"]);
+    library.success(&["--output", "out.txt", "show-tags"]);
+    assert_eq!(library.entries(), [".media_tag.db", "out.txt"]);
+    assert_eq!(
+        fs::read_to_string(library.path("out.txt")).unwrap(),
+        "beach\n"
+    );
+}
+
+#[test]
+fn arguments_are_classified_as_files_or_tags() {
+    let library = TempLibrary::new();
+    library.files(&["a.jpg", "notes"]);
+
+    // Existing files are files whatever their name, everything else is a tag
+    library.success(&["add", "--create", "a.jpg", "notes", "beach"]);
+    assert_eq!(library.tagged("beach"), ["a.jpg", "notes"]);
+
+    // A media extension or a separator marks a file even if it does not exist
+    for file in ["ghost.jpg", "sub/ghost"] {
+        assert!(
+            !library
+                .run(&["add", "--create", file, "sunny"])
+                .status
+                .success()
+        );
+    }
+    // So does `--` for every argument before it
+    assert!(
+        !library
+            .run(&["add", "--create", "ghost", "--", "sunny"])
+            .status
+            .success()
+    );
+    assert_eq!(library.tags(), ["beach"]);
+}
+
+#[test]
+fn tag_interactive_reads_tags_per_file() {
+    let library = TempLibrary::new();
+    library.files(&["a.jpg", "b.jpg", "c.jpg", "d.jpg"]);
+    library.success(&["add", "--create", "b.jpg", "old"]);
+
+    // b.jpg is already tagged and not asked for, c.jpg is skipped and d.jpg never reached
+    library.success_with_stdin(&["tag-interactive"], b"beach night\n\nq\nlate\n");
+    assert_eq!(library.tagged("beach"), ["a.jpg"]);
+    assert_eq!(library.tagged("night"), ["a.jpg"]);
+    assert_eq!(library.tags(), ["old", "beach", "night"]);
+}
+
+#[test]
+fn rename_moves_the_file_back_on_conflict() {
+    let library = TempLibrary::new();
+    library.files(&["a.jpg", "b.jpg"]);
+    library.success(&["add", "--create", "a.jpg", "b.jpg", "beach"]);
+    library.success(&["add", "--create", "a.jpg", "night"]);
+    // b.jpg is still tracked, so the renamed file cannot take over its path
+    fs::remove_file(library.path("b.jpg")).unwrap();
+
+    let output = library.run(&["rename", "a.jpg", "b.jpg"]);
+    assert!(!output.status.success());
+    assert!(library.path("a.jpg").exists());
+    assert!(!library.path("b.jpg").exists());
+    assert_eq!(library.tagged("night"), ["a.jpg"]);
+
+    library.success(&["rename", "a.jpg", "c.jpg"]);
+    assert!(!library.path("a.jpg").exists());
+    assert_eq!(library.tagged("night"), ["c.jpg"]);
+}
+
+// File names on Windows cannot contain line breaks
+#[cfg(unix)]
+#[test]
+fn stdin0_reads_nul_separated_paths() {
+    let library = TempLibrary::new();
+    library.files(&["a.jpg", "sub/new\nline.jpg"]);
+
+    // A trailing NUL ends the last path instead of starting an empty one
+    let stdin = b"a.jpg\0sub/new\nline.jpg\0";
+    library.success_with_stdin(&["add", "--create", "--stdin0", "night"], stdin);
+    assert_eq!(library.tagged("night"), ["a.jpg", "sub/new\nline.jpg"]);
+
+    library.success_with_stdin(&["remove", "--stdin0", "night"], b"sub/new\nline.jpg\0");
+    assert_eq!(library.tagged("night"), ["a.jpg"]);
+}
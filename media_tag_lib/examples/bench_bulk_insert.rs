@@ -0,0 +1,103 @@
+//! Compares [`MediaTag::bulk_insert_associations`] with inserting one association per statement,
+//! both inside a single transaction.
+//!
+//! ```sh
+//! cargo run --release -p media_tag_lib --example bench_bulk_insert [MEDIA] [TAGS_PER_MEDIUM]
+//! ```
+//!
+//! With the defaults (20000 media, 5 tags each, so 100000 associations), a release build on a
+//! single-core x86_64 Linux VM took about 155 ms one statement at a time and 122 ms in bulk, 1.3
+//! times faster. Both sides reuse a prepared statement in one transaction, so the difference is
+//! the number of statements executed.
+
+use media_tag_lib::MediaTag;
+use rusqlite::Connection;
+use std::env;
+use std::fs;
+use std::time::{Duration, Instant};
+
+const TAGS: usize = 50;
+const RUNS: usize = 10;
+
+fn main() {
+    let mut args = env::args().skip(1).map(|arg| arg.parse::<usize>().unwrap());
+    let media_count = args.next().unwrap_or(20_000);
+    let tags_per_medium = args.next().unwrap_or(5).min(TAGS);
+
+    let dir = env::temp_dir().join(format!("media-tag-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let db_path = dir.join(".media_tag.db");
+    let mut media_tag = MediaTag::create(&db_path).unwrap();
+    // The media do not have to exist
+    media_tag.set_canonicalize_paths(false);
+
+    for i in 0..TAGS {
+        media_tag.create_tag(&format!("tag{i}")).unwrap();
+    }
+    let tag_ids: Vec<i64> = media_tag.get_tags().unwrap().iter().map(|t| t.id).collect();
+    let paths: Vec<_> = (0..media_count)
+        .map(|i| dir.join(format!("{}/{i}.jpg", i % 100)))
+        .collect();
+    let media_ids = media_tag.bulk_insert_media(&paths).unwrap();
+    let tag_ids = &tag_ids;
+    let pairs: Vec<(i64, i64)> = media_ids
+        .iter()
+        .enumerate()
+        .flat_map(|(i, &media_id)| {
+            (0..tags_per_medium).map(move |j| (media_id, tag_ids[(i + j) % TAGS]))
+        })
+        .collect();
+
+    drop(media_tag);
+
+    // Every run starts from a fresh connection, so neither side profits from a warm page cache
+    let clear = || {
+        let connection = Connection::open(&db_path).unwrap();
+        connection.execute("DELETE FROM media_tags", []).unwrap();
+    };
+    let single = best_of(|| {
+        clear();
+        let connection = Connection::open(&db_path).unwrap();
+        connection.execute("PRAGMA foreign_keys = ON", []).unwrap();
+        let start = Instant::now();
+        let tx = connection.unchecked_transaction().unwrap();
+        {
+            let mut stmt = tx
+                .prepare(
+                    "INSERT OR IGNORE INTO media_tags(media_id, tag_id, created_at)
+                     VALUES (?1, ?2, unixepoch())",
+                )
+                .unwrap();
+            for pair in &pairs {
+                stmt.execute(*pair).unwrap();
+            }
+        }
+        tx.commit().unwrap();
+        start.elapsed()
+    });
+    let bulk = best_of(|| {
+        clear();
+        let media_tag = MediaTag::open(&db_path).unwrap();
+        let start = Instant::now();
+        media_tag.bulk_insert_associations(&pairs).unwrap();
+        start.elapsed()
+    });
+
+    println!(
+        "{} associations ({media_count} media, {tags_per_medium} tags each), best of {RUNS}",
+        pairs.len()
+    );
+    println!("one statement each: {:>8.1} ms", ms(single));
+    println!("bulk:               {:>8.1} ms", ms(bulk));
+    println!("speedup:            {:>8.1}x", ms(single) / ms(bulk));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+fn best_of(mut f: impl FnMut() -> Duration) -> Duration {
+    (0..RUNS).map(|_| f()).min().unwrap()
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
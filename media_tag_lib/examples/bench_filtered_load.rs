@@ -0,0 +1,98 @@
+//! Compares [`MediaTag::load_media_tag_filtered`] with loading the whole library through
+//! [`MediaTag::load_media_tag`] and filtering it in Rust.
+//!
+//! ```sh
+//! cargo run --release -p media_tag_lib --example bench_filtered_load [MEDIA]
+//! ```
+//!
+//! Every medium gets up to 4 of 50 tags, and the query asks for media with two given tags but not
+//! a third, which matches 226 of the default 50000 media. A release build on a single-core x86_64
+//! Linux VM took about 100 ms to load everything and filter it and 8.5 ms to filter in SQL, about
+//! 12 times faster. Queries matching a larger part of the library gain less.
+
+use media_tag_lib::MediaTag;
+use std::env;
+use std::fs;
+use std::time::{Duration, Instant};
+
+const TAGS: usize = 50;
+const TAGS_PER_MEDIUM: usize = 4;
+const RUNS: usize = 10;
+
+fn main() {
+    let media_count = env::args()
+        .nth(1)
+        .map_or(50_000, |arg| arg.parse().unwrap());
+
+    let dir = env::temp_dir().join(format!("media-tag-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let mut media_tag = MediaTag::create(dir.join(".media_tag.db")).unwrap();
+    // The media do not have to exist
+    media_tag.set_canonicalize_paths(false);
+
+    for i in 0..TAGS {
+        media_tag.create_tag(&format!("tag{i}")).unwrap();
+    }
+    let tag_ids: Vec<i64> = media_tag.get_tags().unwrap().iter().map(|t| t.id).collect();
+    let paths: Vec<_> = (0..media_count)
+        .map(|i| dir.join(format!("{}/{i}.jpg", i % 100)))
+        .collect();
+    let media_ids = media_tag.bulk_insert_media(&paths).unwrap();
+    // A fixed pseudo-random sequence, so every run builds the same library
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut pairs = Vec::with_capacity(media_count * TAGS_PER_MEDIUM);
+    for &media_id in &media_ids {
+        for _ in 0..TAGS_PER_MEDIUM {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            pairs.push((media_id, tag_ids[(state % TAGS as u64) as usize]));
+        }
+    }
+    media_tag.bulk_insert_associations(&pairs).unwrap();
+
+    let include = [tag_ids[0], tag_ids[1]];
+    let exclude = [tag_ids[2]];
+
+    let mut matches = 0;
+    let load_all = best_of(|| {
+        let start = Instant::now();
+        let data = media_tag.load_media_tag().unwrap();
+        let found = data
+            .media
+            .iter()
+            .filter(|medium| {
+                include.iter().all(|id| medium.tags.contains(id))
+                    && !exclude.iter().any(|id| medium.tags.contains(id))
+            })
+            .count();
+        let elapsed = start.elapsed();
+        matches = found;
+        elapsed
+    });
+    let filtered = best_of(|| {
+        let start = Instant::now();
+        let data = media_tag
+            .load_media_tag_filtered(&include, &exclude, false)
+            .unwrap();
+        let elapsed = start.elapsed();
+        assert_eq!(data.media.len(), matches);
+        elapsed
+    });
+
+    println!("{matches} of {media_count} media match, best of {RUNS}");
+    println!("load all and filter: {:>8.1} ms", ms(load_all));
+    println!("filter in SQL:       {:>8.1} ms", ms(filtered));
+    println!("speedup:             {:>8.1}x", ms(load_all) / ms(filtered));
+
+    drop(media_tag);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+fn best_of(mut f: impl FnMut() -> Duration) -> Duration {
+    (0..RUNS).map(|_| f()).min().unwrap()
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
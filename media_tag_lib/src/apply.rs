@@ -0,0 +1,217 @@
+use crate::{Error, MediaTag, Result, parse_stored_path, string_array, validate_tag_name};
+use rusqlite::OptionalExtension;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::Path;
+
+/// How the tags of one file change, see [`MediaTag::set_tags`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TagChange {
+    /// The file as stored in the database (relative to the library root)
+    pub path: String,
+    /// Names of the tags the file gains, sorted
+    pub added: Vec<String>,
+    /// Names of the tags the file loses, sorted
+    pub removed: Vec<String>,
+}
+
+impl TagChange {
+    /// Whether the tags of the file stay the same.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// The changes needed to make the library match a tag spec, see [`MediaTag::apply_spec`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SpecPlan {
+    /// Names of the tags that do not exist yet, sorted
+    pub created_tags: Vec<String>,
+    /// The files whose tags change, in the order of the spec
+    pub changes: Vec<TagChange>,
+}
+
+impl MediaTag {
+    /// Replaces the tags of the file at `path` with `tag_names` in one transaction, tracking the
+    /// file and creating missing tags as needed. Aliases resolve to their tags.
+    pub fn set_tags<P: AsRef<Path>>(&self, path: P, tag_names: &[&str]) -> Result<TagChange> {
+        let path_str = self.resolve_path_to_db_string(path)?;
+
+        let tx = self.connection.unchecked_transaction()?;
+        let result = (|| {
+            let mut tag_id_map = self.get_tag_id_map()?;
+            self.set_stored_tags(
+                &path_str,
+                tag_names,
+                &mut tag_id_map,
+                &mut BTreeSet::new(),
+                true,
+            )
+        })();
+        match result {
+            Ok(change) => {
+                tx.commit()?;
+                self.refresh_tag_cache();
+                Ok(change)
+            }
+            Err(e) => {
+                // Tags created before the failure are rolled back
+                self.refresh_tag_cache();
+                Err(e)
+            }
+        }
+    }
+
+    /// Computes what [`MediaTag::apply_spec`] would change without changing anything.
+    pub fn spec_changes(&self, spec: &str) -> Result<SpecPlan> {
+        self.run_spec(spec, false)
+    }
+
+    /// Makes the tags of every file listed in the TOML tag `spec` exactly the listed ones, like
+    /// [`MediaTag::set_tags`] for each of them, in one transaction. Files that are not listed keep
+    /// their tags. Paths may use either separator but must stay inside the library. The spec has
+    /// the format written by [`MediaTag::export_toml`], of which only the `media` table is read:
+    ///
+    /// ```toml
+    /// [media]
+    /// "photos/beach.jpg" = ["vacation", "summer"]
+    /// "photos/receipt.png" = []
+    /// ```
+    pub fn apply_spec(&self, spec: &str) -> Result<SpecPlan> {
+        let tx = self.connection.unchecked_transaction()?;
+        match self.run_spec(spec, true) {
+            Ok(plan) => {
+                tx.commit()?;
+                self.refresh_tag_cache();
+                Ok(plan)
+            }
+            Err(e) => {
+                // Tags created before the failure are rolled back
+                self.refresh_tag_cache();
+                Err(e)
+            }
+        }
+    }
+
+    fn run_spec(&self, spec: &str, write: bool) -> Result<SpecPlan> {
+        let spec: toml::Table = spec.parse()?;
+        let Some(media) = spec.get("media") else {
+            return Ok(SpecPlan::default());
+        };
+        let media = media
+            .as_table()
+            .ok_or_else(|| Error::InvalidExport("`media` must be a table".to_string()))?;
+
+        let mut tag_id_map = self.get_tag_id_map()?;
+        let mut created = BTreeSet::new();
+        let mut changes = Vec::new();
+        for (path_str, tag_names) in media {
+            let tag_names = string_array(tag_names, path_str)?;
+            let tag_names: Vec<&str> = tag_names.iter().map(String::as_str).collect();
+            let path_str = parse_stored_path(path_str)?;
+
+            let change =
+                self.set_stored_tags(&path_str, &tag_names, &mut tag_id_map, &mut created, write)?;
+            if !change.is_empty() {
+                changes.push(change);
+            }
+        }
+
+        Ok(SpecPlan {
+            created_tags: created.into_iter().collect(),
+            changes,
+        })
+    }
+
+    /// Computes the change that gives the medium stored at `path_str` exactly `tag_names` and
+    /// makes it if `write` is set. `tag_id_map` maps the ids of all tags to their names and is
+    /// kept up to date when tags are created. Tags that do not exist yet are added to `created`.
+    fn set_stored_tags(
+        &self,
+        path_str: &str,
+        tag_names: &[&str],
+        tag_id_map: &mut HashMap<i64, String>,
+        created: &mut BTreeSet<String>,
+        write: bool,
+    ) -> Result<TagChange> {
+        // Tag names mapped to their ids, `None` for tags that do not exist yet
+        let mut wanted: BTreeMap<String, Option<i64>> = BTreeMap::new();
+        for &tag_name in tag_names {
+            validate_tag_name(tag_name)?;
+            match self.get_tag_id(tag_name) {
+                Ok(id) => {
+                    wanted.insert(tag_id_map[&id].clone(), Some(id));
+                }
+                Err(Error::TagDoesNotExist(_)) => {
+                    let name = self.normalize_tag_name(tag_name)?;
+                    validate_tag_name(&name)?;
+                    wanted.insert(name, None);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let medium_id: Option<i64> = self
+            .connection
+            .query_row("SELECT id FROM media WHERE path = ?1", (path_str,), |row| {
+                row.get(0)
+            })
+            .optional()?;
+        let mut current: BTreeMap<String, i64> = BTreeMap::new();
+        if let Some(medium_id) = medium_id {
+            let mut stmt = self
+                .connection
+                .prepare("SELECT tag_id FROM media_tags WHERE media_id = ?1")?;
+            for tag_id in stmt.query_map((medium_id,), |row| row.get::<_, i64>(0))? {
+                let tag_id = tag_id?;
+                current.insert(tag_id_map[&tag_id].clone(), tag_id);
+            }
+        }
+
+        let change = TagChange {
+            path: path_str.to_string(),
+            added: wanted
+                .keys()
+                .filter(|name| !current.contains_key(*name))
+                .cloned()
+                .collect(),
+            removed: current
+                .keys()
+                .filter(|name| !wanted.contains_key(*name))
+                .cloned()
+                .collect(),
+        };
+        let new_tags: Vec<&String> = wanted
+            .iter()
+            .filter(|(_, id)| id.is_none())
+            .map(|(name, _)| name)
+            .collect();
+
+        if write && !change.is_empty() {
+            let medium_id = match medium_id {
+                Some(id) => id,
+                None => self.get_medium_id_or_insert(path_str)?,
+            };
+            for name in &change.added {
+                let tag_id = match wanted[name] {
+                    Some(id) => id,
+                    None => {
+                        self.create_tag(name)?;
+                        let id = self.get_tag_id(name)?;
+                        tag_id_map.insert(id, name.clone());
+                        id
+                    }
+                };
+                self.add_tag_id_to_medium(medium_id, tag_id)?;
+            }
+            for name in &change.removed {
+                self.connection.execute(
+                    "DELETE FROM media_tags WHERE media_id = ?1 AND tag_id = ?2",
+                    (medium_id, current[name]),
+                )?;
+            }
+        }
+        created.extend(new_tags.into_iter().cloned());
+
+        Ok(change)
+    }
+}
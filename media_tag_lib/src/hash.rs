@@ -0,0 +1,188 @@
+use crate::{Error, MediaTag, Result, from_db_string};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+/// Algorithm used to hash the contents of files for finding duplicates. The name of the algorithm
+/// is stored next to each hash, so hashes of different algorithms are never compared.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HashAlgo {
+    /// SHA-256, available everywhere
+    #[default]
+    Sha256,
+    /// BLAKE3, considerably faster (requires the `blake3` feature)
+    #[cfg(feature = "blake3")]
+    Blake3,
+}
+
+impl HashAlgo {
+    pub const ALL: &[HashAlgo] = &[
+        HashAlgo::Sha256,
+        #[cfg(feature = "blake3")]
+        HashAlgo::Blake3,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            #[cfg(feature = "blake3")]
+            Self::Blake3 => "blake3",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|algo| algo.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Hashes the contents of the file at `path`, returning the digest as lowercase hex.
+    pub fn hash_file<P: AsRef<Path>>(self, path: P) -> io::Result<String> {
+        let mut file = File::open(path)?;
+        match self {
+            Self::Sha256 => {
+                let mut hasher = Sha256::new();
+                io::copy(&mut file, &mut hasher)?;
+                Ok(to_hex(&hasher.finalize()))
+            }
+            #[cfg(feature = "blake3")]
+            Self::Blake3 => {
+                let mut hasher = blake3::Hasher::new();
+                io::copy(&mut file, &mut hasher)?;
+                Ok(to_hex(hasher.finalize().as_bytes()))
+            }
+        }
+    }
+}
+
+impl fmt::Display for HashAlgo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl MediaTag {
+    /// Stores the content hash of every tracked file that has no hash computed with `algo` yet,
+    /// or of all files if `rehash` is set (hashes are not updated when a file changes). Files
+    /// that cannot be read are reported to `on_error` and skipped. Returns the number of hashed
+    /// files.
+    pub fn hash_media<F: FnMut(&Path, Error)>(
+        &self,
+        algo: HashAlgo,
+        rehash: bool,
+        on_error: F,
+    ) -> Result<usize> {
+        self.hash_media_threaded(algo, rehash, 1, on_error)
+    }
+
+    /// Like [`MediaTag::hash_media`], but reads and hashes up to `threads` files at once. The
+    /// hashes are written to the database by the calling thread as they arrive, so the connection
+    /// is never shared between threads.
+    ///
+    /// On SSDs hashing scales with the number of cores. On spinning disks more than one or two
+    /// threads usually make it slower, as the disk has to seek between the files.
+    pub fn hash_media_threaded<F: FnMut(&Path, Error)>(
+        &self,
+        algo: HashAlgo,
+        rehash: bool,
+        threads: usize,
+        mut on_error: F,
+    ) -> Result<usize> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, path FROM media
+             WHERE ?1 OR content_hash IS NULL OR hash_algo IS NOT ?2",
+        )?;
+        let media = stmt
+            .query_map((rehash, algo.name()), |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let root = self.root()?;
+        let media: Vec<(i64, PathBuf)> = media
+            .into_iter()
+            .map(|(id, path_str)| (id, from_db_string(root, &path_str)))
+            .collect();
+
+        let tx = self.connection.unchecked_transaction()?;
+        let mut hashed = 0;
+        // Index of the next file to hash, shared by the worker threads
+        let next = AtomicUsize::new(0);
+        thread::scope(|scope| -> Result<()> {
+            let (sender, receiver) = mpsc::channel();
+            for _ in 0..threads.clamp(1, media.len().max(1)) {
+                let (sender, next, media) = (sender.clone(), &next, &media);
+                scope.spawn(move || {
+                    while let Some((id, path)) = media.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        // Fails once the receiving side gave up after a database error
+                        if sender.send((*id, path, algo.hash_file(path))).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(sender);
+
+            let mut update =
+                tx.prepare("UPDATE media SET content_hash = ?1, hash_algo = ?2 WHERE id = ?3")?;
+            for (id, path, hash) in receiver {
+                match hash {
+                    Ok(hash) => {
+                        update.execute((hash, algo.name(), id))?;
+                        hashed += 1;
+                    }
+                    Err(e) => on_error(path, e.into()),
+                }
+            }
+            Ok(())
+        })?;
+        tx.commit()?;
+
+        Ok(hashed)
+    }
+
+    /// Groups the tracked files whose stored content hashes are equal, see
+    /// [`MediaTag::hash_media`]. Only groups with more than one file are returned.
+    pub fn find_duplicates(&self) -> Result<Vec<Vec<PathBuf>>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT hash_algo, content_hash, path FROM media
+             WHERE content_hash IS NOT NULL
+             ORDER BY hash_algo, content_hash, path",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let root = self.media_root();
+        let mut groups: Vec<Vec<PathBuf>> = Vec::new();
+        let mut previous: Option<(String, String)> = None;
+        for (algo, hash, path_str) in rows {
+            let path = from_db_string(root, &path_str);
+            let key = Some((algo, hash));
+            match groups.last_mut() {
+                Some(group) if key == previous => group.push(path),
+                _ => groups.push(vec![path]),
+            }
+            previous = key;
+        }
+        groups.retain(|group| group.len() > 1);
+
+        Ok(groups)
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
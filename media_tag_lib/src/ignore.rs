@@ -0,0 +1,116 @@
+use crate::Result;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+pub const IGNORE_FILENAME: &str = ".mediatagignore";
+
+struct Pattern {
+    glob: Vec<char>,
+    negated: bool,
+    dir_only: bool,
+    anchored: bool,
+}
+
+/// Glob patterns from a `.mediatagignore` file at the library root.
+///
+/// The syntax follows `.gitignore`: one pattern per line, blank lines and lines starting with `#`
+/// are skipped. `*` matches anything except `/`, `**` matches across directories and `?` matches a
+/// single character. A trailing `/` only matches directories. Patterns containing a `/` are
+/// matched against the path relative to the library root, all others against the file name. A
+/// leading `!` re-includes paths excluded by an earlier pattern; the last matching pattern wins.
+#[derive(Default)]
+pub struct IgnoreSet {
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreSet {
+    pub fn parse(contents: &str) -> Self {
+        let patterns = contents
+            .lines()
+            .map(|line| line.trim_end())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let (negated, line) = match line.strip_prefix('!') {
+                    Some(rest) => (true, rest),
+                    None => (false, line),
+                };
+                let (dir_only, line) = match line.strip_suffix('/') {
+                    Some(rest) => (true, rest),
+                    None => (false, line),
+                };
+                let anchored = line.contains('/');
+                Pattern {
+                    glob: line.trim_start_matches('/').chars().collect(),
+                    negated,
+                    dir_only,
+                    anchored,
+                }
+            })
+            .collect();
+
+        Self { patterns }
+    }
+
+    /// Loads the ignore file of the library at `root`. A missing file yields an empty set.
+    pub fn load<P: AsRef<Path>>(root: P) -> Result<Self> {
+        match fs::read_to_string(root.as_ref().join(IGNORE_FILENAME)) {
+            Ok(contents) => Ok(Self::parse(&contents)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Whether `rel_path` (relative to the library root) is excluded.
+    pub fn is_ignored<P: AsRef<Path>>(&self, rel_path: P, is_dir: bool) -> bool {
+        let rel_path = rel_path.as_ref();
+        let full: Vec<char> = rel_path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+            .chars()
+            .collect();
+        let name: Vec<char> = rel_path
+            .file_name()
+            .map(|n| n.to_string_lossy().chars().collect())
+            .unwrap_or_default();
+
+        let mut ignored = false;
+        for pattern in &self.patterns {
+            if pattern.dir_only && !is_dir {
+                continue;
+            }
+            let text = if pattern.anchored { &full } else { &name };
+            if glob_match(&pattern.glob, text) {
+                ignored = !pattern.negated;
+            }
+        }
+        ignored
+    }
+}
+
+/// Whether the path `text` (with `/` as separator) matches the glob `pattern`, using the syntax of
+/// `.mediatagignore` patterns: `*` and `?` do not match `/`, `**` matches across directories.
+pub fn glob_matches(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    glob_match(&pattern, &text)
+}
+
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', '/', rest @ ..] => (0..=text.len())
+            .filter(|&i| i == 0 || text[i - 1] == '/')
+            .any(|i| glob_match(rest, &text[i..])),
+        ['*', '*', rest @ ..] => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+        ['*', rest @ ..] => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != '/')
+            .any(|i| glob_match(rest, &text[i..])),
+        ['?', rest @ ..] => {
+            matches!(text.first(), Some(&c) if c != '/') && glob_match(rest, &text[1..])
+        }
+        [c, rest @ ..] => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    }
+}
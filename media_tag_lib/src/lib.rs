@@ -1,23 +1,82 @@
-use rusqlite::{Connection, OptionalExtension};
-use std::collections::HashMap;
+mod apply;
+mod hash;
+mod ignore;
+mod lock;
+mod ndjson;
+mod saved_query;
+mod tag_case;
+mod walk;
+
+pub use apply::{SpecPlan, TagChange};
+pub use hash::HashAlgo;
+pub use ignore::{IGNORE_FILENAME, IgnoreSet, glob_matches};
+pub use lock::{LOCK_FILENAME, LibraryLock};
+pub use ndjson::ImportSummary;
+pub use saved_query::SearchQuery;
+pub use tag_case::TagCase;
+pub use walk::WalkOptions;
+
+use rusqlite::trace::{TraceEvent, TraceEventCodes};
+use rusqlite::types::Value;
+use rusqlite::{Connection, ErrorCode, OptionalExtension, Params, params_from_iter};
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
 use std::fmt;
-use std::path::{Path, PathBuf, StripPrefixError};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::path::{Component, Path, PathBuf, StripPrefixError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Debug)]
 pub enum Error {
     SqliteError(rusqlite::Error),
+    DatabaseBusy(rusqlite::Error),
+    LibraryBusy(PathBuf),
     TagAlreadyExists(String),
+    InvalidTagName(String, &'static str),
     TagDoesNotExist(String),
+    TagIdDoesNotExist(i64),
     FileDoesNotExist(String),
+    FileAlreadyTracked(String),
     CouldNotDetermineMediaTagPath,
+    RootUnavailable(PathBuf, std::io::Error),
     IoError(std::io::Error),
     StripPrefixError(StripPrefixError),
+    OutsideLibraryRoot {
+        path: PathBuf,
+        root: PathBuf,
+    },
     InvalidPathEncoding(PathBuf),
+    AmbiguousFileName(String, Vec<PathBuf>),
+    DatabaseAlreadyExists(PathBuf),
+    DatabaseDoesNotExist(PathBuf),
+    CsvError(csv::Error),
+    AssociationAlreadyExists(String, String),
+    UnsupportedExportVersion(String),
+    InvalidExport(String),
+    InvalidLine(usize, Box<Error>),
+    TomlError(toml::de::Error),
+    JsonError(serde_json::Error),
+    SchemaMismatch {
+        expected: String,
+        found: Option<String>,
+    },
+    UnknownSchemaVersion(usize),
+    UnknownTagCase(String),
+    InvalidPattern(String),
+    AmbiguousTagName(String),
+    InvalidRating(u8),
+    QueryDoesNotExist(String),
 }
 
 impl From<rusqlite::Error> for Error {
     fn from(e: rusqlite::Error) -> Self {
-        Self::SqliteError(e)
+        match e.sqlite_error_code() {
+            Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => Self::DatabaseBusy(e),
+            _ => Self::SqliteError(e),
+        }
     }
 }
 impl From<std::io::Error> for Error {
@@ -30,22 +89,104 @@ impl From<StripPrefixError> for Error {
         Self::StripPrefixError(e)
     }
 }
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Self {
+        Self::CsvError(e)
+    }
+}
+impl From<toml::de::Error> for Error {
+    fn from(e: toml::de::Error) -> Self {
+        Self::TomlError(e)
+    }
+}
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Self::JsonError(e)
+    }
+}
 impl std::error::Error for Error {}
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::SqliteError(e) => write!(f, "Database error: {e}"),
+            Self::DatabaseBusy(e) => write!(
+                f,
+                "Database is locked by another process and did not become available in time: {e}"
+            ),
+            Self::LibraryBusy(lock) => write!(
+                f,
+                "Library is busy: another command holds {} (delete it if no other media tag \
+                 command is running)",
+                lock.display()
+            ),
             Self::TagAlreadyExists(t) => write!(f, "Tag \"{t}\" already exists"),
+            Self::InvalidTagName(t, reason) => write!(f, "Invalid tag name \"{t}\": {reason}"),
             Self::TagDoesNotExist(t) => write!(f, "Tag \"{t}\" does not exist"),
+            Self::TagIdDoesNotExist(id) => write!(f, "There is no tag with id {id}"),
             Self::FileDoesNotExist(p) => write!(f, "File not found in database: {p}"),
+            Self::FileAlreadyTracked(p) => write!(f, "File is already in database: {p}"),
             Self::CouldNotDetermineMediaTagPath => {
                 write!(f, "Failed to determine library root path")
             }
+            Self::RootUnavailable(root, e) => {
+                write!(f, "Library root {} is not accessible: {e}", root.display())
+            }
             Self::IoError(e) => write!(f, "IO operation failed: {e}"),
             Self::StripPrefixError(e) => write!(f, "Path is not inside library root: {e}"),
+            Self::OutsideLibraryRoot { path, root } => write!(
+                f,
+                "{} is outside of the library at {}",
+                path.display(),
+                root.display()
+            ),
             Self::InvalidPathEncoding(p) => {
                 write!(f, "Path contains invalid UTF-8 characters: {}", p.display())
             }
+            Self::AmbiguousFileName(p, candidates) => {
+                write!(f, "\"{p}\" matches more than one file in database:")?;
+                for candidate in candidates {
+                    write!(f, "\n  {}", candidate.display())?;
+                }
+                Ok(())
+            }
+            Self::DatabaseAlreadyExists(p) => {
+                write!(f, "Database already exists: {}", p.display())
+            }
+            Self::DatabaseDoesNotExist(p) => {
+                write!(f, "Database does not exist: {}", p.display())
+            }
+            Self::CsvError(e) => write!(f, "CSV error: {e}"),
+            Self::TomlError(e) => write!(f, "TOML error: {e}"),
+            Self::JsonError(e) => write!(f, "JSON error: {e}"),
+            Self::InvalidExport(e) => write!(f, "Invalid export: {e}"),
+            Self::InvalidLine(line, e) => write!(f, "Line {line}: {e}"),
+            Self::AssociationAlreadyExists(p, t) => write!(f, "File {p} already has tag \"{t}\""),
+            Self::UnsupportedExportVersion(v) => write!(
+                f,
+                "Unsupported export format version {v} (supported up to {EXPORT_FORMAT_VERSION})"
+            ),
+            Self::SchemaMismatch { expected, found } => write!(
+                f,
+                "Database schema {} does not match the expected schema {expected}, the database \
+                 has to be migrated",
+                found.as_deref().unwrap_or("(unknown)")
+            ),
+            Self::UnknownSchemaVersion(v) => write!(
+                f,
+                "Database schema version {v} is newer than this version of media tag supports"
+            ),
+            Self::UnknownTagCase(c) => write!(f, "Unknown tag casing \"{c}\" in database"),
+            Self::InvalidPattern(p) => write!(f, "Invalid pattern: {p}"),
+            Self::QueryDoesNotExist(q) => write!(f, "There is no saved query named \"{q}\""),
+            Self::InvalidRating(r) => {
+                write!(
+                    f,
+                    "Invalid rating {r}, ratings range from 0 to {MAX_RATING}"
+                )
+            }
+            Self::AmbiguousTagName(t) => {
+                write!(f, "\"{t}\" matches more than one tag when ignoring case")
+            }
         }
     }
 }
@@ -54,19 +195,123 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 pub struct MediaTag {
     connection: Connection,
+    /// The library root as given (made absolute), see [`MediaTag::root`]
     root: PathBuf,
+    /// The canonicalized root, resolved on first use so that commands which do not access files
+    /// work while the library's volume is not mounted
+    canonical_root: OnceCell<PathBuf>,
+    /// Tag names and aliases mapped to their ids, loaded on the first lookup and dropped whenever
+    /// tags or aliases are created or deleted.
+    tag_ids: RefCell<Option<HashMap<String, i64>>>,
+    /// The casing of tag names stored in the `meta` table, loaded on first use
+    tag_case: Cell<Option<TagCase>>,
+    /// Whether paths are resolved on the file system, see [`MediaTag::set_canonicalize_paths`]
+    canonicalize_paths: bool,
+    /// Replaces the root in the paths of loaded media, see [`MediaTag::set_output_root`]
+    output_root: Option<PathBuf>,
+    /// How often busy writes are retried, see [`MediaTag::set_max_retries`]
+    max_retries: u32,
 }
 
 pub struct Tag {
     pub id: i64,
     pub name: String,
+    pub color: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Usage details of a single tag, see [`MediaTag::tag_detail`].
+pub struct TagDetail {
+    pub tag: Tag,
+    /// Number of media the tag is assigned to
+    pub files: usize,
+    /// Unix timestamp of the oldest assignment, `None` if the tag is unused or all of its
+    /// assignments predate timestamps
+    pub first_used: Option<i64>,
+    /// Unix timestamp of the newest assignment
+    pub last_used: Option<i64>,
+    /// Up to [`TAG_DETAIL_SAMPLE_SIZE`] media with the tag, most recently tagged first
+    pub sample: Vec<PathBuf>,
 }
 
+/// How many paths [`MediaTag::tag_detail`] returns as a sample.
+pub const TAG_DETAIL_SAMPLE_SIZE: usize = 5;
+
 #[derive(Clone)]
 pub struct Medium {
     pub id: i64,
+    /// The absolute path of the medium, below the library root
     pub path: PathBuf,
+    /// The path of the medium relative to the library root, as it is stored in the database
+    pub relative: PathBuf,
+    /// The ids of the medium's tags, ordered by tag name
     pub tags: Vec<i64>,
+    pub note: Option<String>,
+    /// Between 0 and [`MAX_RATING`], `None` if the medium was not rated
+    pub rating: Option<u8>,
+    /// Modification time of the file in seconds since the Unix epoch, recorded whenever the file
+    /// is tracked or tagged (see [`MediaTag::update_mtimes`]). `None` if it was never recorded.
+    pub mtime: Option<i64>,
+}
+
+/// Media are equal if they have the same id, regardless of their loaded path, tags and note. This
+/// allows combining the results of several queries in a `HashSet`.
+impl PartialEq for Medium {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Medium {}
+
+impl Hash for Medium {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+/// How [`MediaTag::import_csv`] treats files that already have tags.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the existing tags and add the imported ones
+    #[default]
+    Skip,
+    /// Fail if a file already has one of the imported tags
+    Error,
+    /// Replace the tags of every imported file with the imported ones
+    Replace,
+}
+
+/// What [`MediaTag::add_tag_with`] does when the tag to add does not exist.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MissingTag {
+    /// Fail with [`Error::TagDoesNotExist`], so misspelled tags are noticed
+    #[default]
+    Error,
+    /// Create the tag
+    Create,
+}
+
+/// How [`MediaTag::get_tags_ordered`] and [`MediaTag::get_tags_filtered`] sort the tags.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TagOrder {
+    /// Alphabetically
+    Name,
+    /// In the order the tags were created, like [`MediaTag::get_tags`]
+    #[default]
+    Id,
+    /// Most used first, then by name
+    Count,
+}
+
+/// The rows a destructive operation would change, returned by the `*_plan` methods (like
+/// [`MediaTag::delete_tag_plan`]) so frontends can preview an operation before running it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChangePlan {
+    /// Names of the tags that would be deleted
+    pub deleted_tags: Vec<String>,
+    /// Number of tag assignments that would be removed
+    pub removed_associations: usize,
 }
 
 pub struct MediaTags {
@@ -74,10 +319,175 @@ pub struct MediaTags {
     pub media: Vec<Medium>,
 }
 
+impl MediaTags {
+    /// Resolves the tag ids of `medium` to their names, sorted alphabetically.
+    pub fn names(&self, medium: &Medium) -> Vec<&str> {
+        let mut names: Vec<&str> = medium
+            .tags
+            .iter()
+            .filter_map(|id| self.tags.get(id).map(|s| s.as_str()))
+            .collect();
+        names.sort_unstable();
+        names
+    }
+}
+
 static SQL_SCRIPT: &str = include_str!("./db.sqlite");
 
+/// Schema changes applied on top of `SQL_SCRIPT`, in order. The number of migrations a database
+/// has already received is tracked in its `user_version`.
+static MIGRATIONS: &[&str] = &[
+    "ALTER TABLE media_tags ADD COLUMN created_at INTEGER;",
+    "ALTER TABLE tags ADD COLUMN color TEXT;
+     ALTER TABLE tags ADD COLUMN description TEXT;",
+    "ALTER TABLE media ADD COLUMN note TEXT;",
+    "ALTER TABLE media ADD COLUMN content_hash TEXT;
+     ALTER TABLE media ADD COLUMN hash_algo TEXT;",
+    "ALTER TABLE media ADD COLUMN rating INTEGER;",
+    "ALTER TABLE media ADD COLUMN mtime INTEGER;",
+];
+
+/// The highest rating a medium can have, see [`MediaTag::set_rating`].
+pub const MAX_RATING: u8 = 5;
+
+/// How long operations wait for another process to release its lock on the database.
+pub const DEFAULT_BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often writes that fail with [`Error::DatabaseBusy`] are retried, see
+/// [`MediaTag::set_max_retries`].
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+/// The wait before the first retry of a busy write, doubled for every further retry.
+const RETRY_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Upper bound for bound parameters in a single statement (SQLite's conservative default limit).
+const MAX_SQL_VARIABLES: usize = 999;
+
+/// How many files [`MediaTag::write_csv`] and [`MediaTag::import_csv_batched`] hold in memory at
+/// once by default.
+pub const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// Version of the format written by [`MediaTag::export_csv`]. Bump it whenever the format changes
+/// in a way older versions would misread.
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+/// Prefix of the first line of an export, followed by [`EXPORT_FORMAT_VERSION`].
+const EXPORT_HEADER: &str = "# media_tag export format ";
+
+pub const SIDECAR_EXTENSION: &str = "tags";
+
+/// Checks that `name` can be used as the name of a new tag. Names must not be empty or consist of
+/// whitespace only, and must not contain commas, semicolons or line breaks, which separate tags in
+/// the output of `status`, exports and saved queries.
+pub fn validate_tag_name(name: &str) -> Result<()> {
+    let reason = if name.trim().is_empty() {
+        "must not be empty"
+    } else if name.contains([',', ';']) {
+        "must not contain commas or semicolons"
+    } else if name.contains(['\n', '\r']) {
+        "must not contain line breaks"
+    } else {
+        return Ok(());
+    };
+    Err(Error::InvalidTagName(name.to_string(), reason))
+}
+
+/// Returns the path of the sidecar file belonging to `path` (`photo.jpg` -> `photo.jpg.tags`).
+pub fn sidecar_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let mut sidecar = OsString::from(path.as_ref().as_os_str());
+    sidecar.push(".");
+    sidecar.push(SIDECAR_EXTENSION);
+    PathBuf::from(sidecar)
+}
+
 impl MediaTag {
+    /// Equivalent to [`MediaTag::open_or_create`].
     pub fn new<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::open_or_create(path)
+    }
+
+    /// Creates a new database at `path`. Fails with [`Error::DatabaseAlreadyExists`] if the file
+    /// already exists.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+        {
+            Ok(_) => Self::open_or_create(path),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                Err(Error::DatabaseAlreadyExists(path.to_path_buf()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Opens an existing database. Fails with [`Error::DatabaseDoesNotExist`] if there is no file
+    /// at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+
+        if !path.is_file() {
+            return Err(Error::DatabaseDoesNotExist(path.to_path_buf()));
+        }
+        Self::open_or_create(path)
+    }
+
+    /// Opens the database at `path`, creating it if it does not exist yet. Fails with
+    /// [`Error::SchemaMismatch`] if an existing database was created or last migrated with a
+    /// different schema, see [`MediaTag::open_and_migrate`].
+    pub fn open_or_create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let media_tag = Self::connect(path)?;
+
+        let is_empty: bool = media_tag.connection.query_row(
+            "SELECT NOT EXISTS(SELECT 1 FROM sqlite_master)",
+            [],
+            |row| row.get(0),
+        )?;
+        if is_empty {
+            media_tag.migrate()?;
+        } else {
+            let found = media_tag.stored_schema_checksum()?;
+            let expected = schema_checksum();
+            if found.as_deref() != Some(expected.as_str()) {
+                return Err(Error::SchemaMismatch { expected, found });
+            }
+        }
+
+        Ok(media_tag)
+    }
+
+    /// Opens an existing database and brings its schema up to date. This is required after
+    /// upgrading to a version of media tag with a changed schema.
+    pub fn open_and_migrate<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+
+        if !path.is_file() {
+            return Err(Error::DatabaseDoesNotExist(path.to_path_buf()));
+        }
+        let media_tag = Self::connect(path)?;
+        media_tag.migrate()?;
+
+        Ok(media_tag)
+    }
+
+    /// Adopts an already open connection, e.g. to keep the library in the same SQLite file as the
+    /// data of a host application, and creates or migrates the schema in it. Paths are resolved
+    /// against `root` rather than the directory of the database file.
+    ///
+    /// The tables (`meta`, `tags`, `tag_aliases`, `media`, `media_tags` and `saved_queries`)
+    /// share their namespace with the host's tables, and the applied migrations are counted in
+    /// the `user_version` pragma, so the host must not use any of them.
+    pub fn from_connection(connection: Connection, root: PathBuf) -> Result<Self> {
+        let media_tag = Self::adopt(connection, normalize_path(&root)?)?;
+        media_tag.migrate()?;
+
+        Ok(media_tag)
+    }
+
+    fn connect<P: AsRef<Path>>(path: P) -> Result<Self> {
         let path = path.as_ref();
 
         let parent = path.parent().ok_or(Error::CouldNotDetermineMediaTagPath)?;
@@ -86,46 +496,331 @@ impl MediaTag {
             Path::new(".")
         } else {
             parent
-        }
-        .canonicalize()?;
+        };
+        let root = normalize_path(root)?;
 
-        let connection = Connection::open(path)?;
-        connection.execute_batch(SQL_SCRIPT)?;
+        Self::adopt(Connection::open(path)?, root)
+    }
 
+    fn adopt(connection: Connection, root: PathBuf) -> Result<Self> {
+        // The callback has a cost for every statement, so it is only registered when logging
+        if log::log_enabled!(log::Level::Debug) {
+            connection.trace_v2(TraceEventCodes::SQLITE_TRACE_PROFILE, Some(log_statement));
+        }
         connection.execute("PRAGMA foreign_keys = ON;", [])?;
+        connection.busy_timeout(DEFAULT_BUSY_TIMEOUT)?;
+
+        Ok(Self {
+            connection,
+            root,
+            canonical_root: OnceCell::new(),
+            tag_ids: RefCell::default(),
+            tag_case: Cell::default(),
+            canonicalize_paths: true,
+            output_root: None,
+            max_retries: DEFAULT_MAX_RETRIES,
+        })
+    }
+
+    /// Sets how long operations wait for a lock held by another process before failing with
+    /// [`Error::DatabaseBusy`]. Defaults to [`DEFAULT_BUSY_TIMEOUT`].
+    pub fn set_busy_timeout(&self, timeout: Duration) -> Result<()> {
+        self.connection.busy_timeout(timeout)?;
+        Ok(())
+    }
+
+    /// Sets how often adding and removing tags and the bulk inserts are retried when they fail
+    /// with [`Error::DatabaseBusy`] even after waiting for the busy timeout, which can happen when
+    /// several processes write at once. The waits between retries start at 50ms and double every
+    /// time. `0` disables retrying. Defaults to [`DEFAULT_MAX_RETRIES`].
+    pub fn set_max_retries(&mut self, retries: u32) {
+        self.max_retries = retries;
+    }
+
+    /// Runs the write `f`, running it again with exponential backoff while it fails with
+    /// [`Error::DatabaseBusy`], at most [`MediaTag::set_max_retries`] times. `f` must leave
+    /// nothing behind when it fails, e.g. by doing its work in a transaction. Inside a
+    /// transaction of the caller, which a failed statement does not roll back, `f` is not retried.
+    fn with_retries<T>(&self, mut f: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            match f() {
+                Err(Error::DatabaseBusy(e))
+                    if attempt < self.max_retries && self.connection.is_autocommit() =>
+                {
+                    let delay = RETRY_BACKOFF * 2u32.pow(attempt);
+                    log::debug!("database is busy ({e}), retrying in {delay:?}");
+                    std::thread::sleep(delay);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
 
-        Ok(Self { connection, root })
+    /// Sets whether paths are canonicalized before they are stored or looked up (the default).
+    /// Canonicalizing resolves symbolic links and requires the file to exist. Without it, paths
+    /// are only normalized lexically (removing `.` and `..`), which allows tagging files on a
+    /// volume that is not mounted. Then different paths leading to the same file, e.g. through a
+    /// symbolic link, are no longer detected as one file.
+    pub fn set_canonicalize_paths(&mut self, canonicalize: bool) {
+        self.canonicalize_paths = canonicalize;
+    }
+
+    /// Sets the directory that [`Medium::path`] and the paths in [`TagDetail::sample`] are built
+    /// from instead of the library root, e.g. to view a copy of the database whose files live
+    /// somewhere else. Only the paths of loaded media change: files passed to other operations
+    /// are still resolved against the library root and nothing stored is rewritten.
+    pub fn set_output_root(&mut self, root: Option<PathBuf>) {
+        self.output_root = root;
+    }
+
+    /// Creates missing tables, applies pending migrations and records the resulting schema, all
+    /// in one transaction.
+    fn migrate(&self) -> Result<()> {
+        let tx = self.connection.unchecked_transaction()?;
+
+        tx.execute_batch(SQL_SCRIPT)?;
+        apply_migrations(&tx)?;
+        tx.execute(
+            "INSERT OR REPLACE INTO meta(key, value) VALUES ('schema_checksum', ?1)",
+            (schema_checksum(),),
+        )?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn stored_schema_checksum(&self) -> Result<Option<String>> {
+        let has_meta: bool = self.connection.query_row(
+            "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'meta')",
+            [],
+            |row| row.get(0),
+        )?;
+        if !has_meta {
+            return Ok(None);
+        }
+
+        Ok(self
+            .connection
+            .query_row(
+                "SELECT value FROM meta WHERE key = 'schema_checksum'",
+                [],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Returns the checksum of the schema the database was created or last migrated with.
+    pub fn schema_version(&self) -> Result<String> {
+        self.stored_schema_checksum()?.ok_or(Error::SchemaMismatch {
+            expected: schema_checksum(),
+            found: None,
+        })
+    }
+
+    /// Returns the canonicalized library root, failing if it is not accessible (e.g. on a volume
+    /// that is not mounted).
+    fn root(&self) -> Result<&Path> {
+        if let Some(root) = self.canonical_root.get() {
+            return Ok(root);
+        }
+        let root = self
+            .root
+            .canonicalize()
+            .map_err(|e| Error::RootUnavailable(self.root.clone(), e))?;
+        Ok(self.canonical_root.get_or_init(|| root))
+    }
+
+    /// The root to build the paths of media from: canonicalized if possible, as given otherwise,
+    /// so that media can still be listed while the library is not accessible.
+    fn media_root(&self) -> &Path {
+        self.root().unwrap_or(&self.root)
+    }
+
+    /// The root to build the paths of loaded media from, see [`MediaTag::set_output_root`].
+    fn output_root(&self) -> &Path {
+        self.output_root
+            .as_deref()
+            .unwrap_or_else(|| self.media_root())
+    }
+
+    /// Makes an absolute path relative to the library root, failing with
+    /// [`Error::OutsideLibraryRoot`] if it is not inside the library.
+    fn strip_root<'a>(&self, abs_path: &'a Path) -> Result<&'a Path> {
+        let root = self.root()?;
+        abs_path
+            .strip_prefix(root)
+            .map_err(|_| Error::OutsideLibraryRoot {
+                path: abs_path.to_path_buf(),
+                root: root.to_path_buf(),
+            })
     }
 
     fn resolve_path_to_db_string<P: AsRef<Path>>(&self, path: P) -> Result<String> {
         let path = path.as_ref();
-        let abs_path = path.canonicalize()?;
-        let rel_path = abs_path.strip_prefix(&self.root)?;
+        let abs_path = if self.canonicalize_paths {
+            path.canonicalize()?
+        } else {
+            normalize_path(path)?
+        };
+        let rel_path = self.strip_root(&abs_path)?;
+        log::debug!("resolved {} to {}", path.display(), rel_path.display());
+
+        to_db_string(rel_path)
+    }
+
+    /// Like [`MediaTag::resolve_path_to_db_string`], but only the parent directory of `path` has
+    /// to exist. Used for files that were moved or are about to be.
+    fn resolve_missing_path_to_db_string<P: AsRef<Path>>(&self, path: P) -> Result<String> {
+        let path = path.as_ref();
+        if !self.canonicalize_paths {
+            return self.resolve_path_to_db_string(path);
+        }
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| Error::FileDoesNotExist(path.display().to_string()))?;
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let abs_path = parent.canonicalize()?.join(file_name);
+        let rel_path = self.strip_root(&abs_path)?;
+        log::debug!("resolved {} to {}", path.display(), rel_path.display());
 
-        rel_path
-            .to_str()
-            .map(|s| s.to_string())
-            .ok_or_else(|| Error::InvalidPathEncoding(rel_path.to_path_buf()))
+        to_db_string(rel_path)
     }
 
     pub fn create_tag(&self, name: &str) -> Result<()> {
-        let affected = self
-            .connection
-            .execute("INSERT OR IGNORE INTO tags (name) VALUES (?1)", (name,))?;
+        self.create_tag_full(name, None, None)
+    }
+
+    /// Creates a tag along with an optional display color and description.
+    pub fn create_tag_full(
+        &self,
+        name: &str,
+        color: Option<&str>,
+        description: Option<&str>,
+    ) -> Result<()> {
+        let name = &self.normalize_tag_name(name)?;
+        validate_tag_name(name)?;
+        let affected = self.connection.execute(
+            "INSERT OR IGNORE INTO tags (name, color, description) VALUES (?1, ?2, ?3)",
+            (name, color, description),
+        )?;
 
         if affected == 0 {
             return Err(Error::TagAlreadyExists(name.to_string()));
         }
+        self.refresh_tag_cache();
+        Ok(())
+    }
+
+    /// Drops the cached tag ids so they are reloaded on the next lookup. This is only needed if
+    /// tags were created or deleted through another connection to the database.
+    pub fn refresh_tag_cache(&self) {
+        self.tag_ids.take();
+    }
+
+    /// Makes the tag `tag_name` also available as `alias` wherever tags are looked up by name.
+    pub fn add_tag_alias(&self, alias: &str, tag_name: &str) -> Result<()> {
+        let alias = &self.normalize_tag_name(alias)?;
+        validate_tag_name(alias)?;
+        let tag_id = self.get_tag_id(tag_name)?;
+        if self.get_tag_id(alias).is_ok() {
+            return Err(Error::TagAlreadyExists(alias.to_string()));
+        }
+
+        self.connection.execute(
+            "INSERT INTO tag_aliases (alias, tag_id) VALUES (?1, ?2)",
+            (alias, tag_id),
+        )?;
+        self.refresh_tag_cache();
         Ok(())
     }
 
+    /// Like [`MediaTag::get_tags`], along with the aliases of every tag sorted alphabetically.
+    pub fn get_tags_with_aliases(&self) -> Result<Vec<(Tag, Vec<String>)>> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT tag_id, alias FROM tag_aliases ORDER BY alias")?;
+        let mut aliases: HashMap<i64, Vec<String>> = HashMap::new();
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            aliases.entry(row.get(0)?).or_default().push(row.get(1)?);
+        }
+
+        Ok(self
+            .get_tags()?
+            .into_iter()
+            .map(|tag| {
+                let tag_aliases = aliases.remove(&tag.id).unwrap_or_default();
+                (tag, tag_aliases)
+            })
+            .collect())
+    }
+
+    /// Returns all tags in the order they were created.
     pub fn get_tags(&self) -> Result<Vec<Tag>> {
-        let mut stmt = self.connection.prepare("SELECT id, name FROM tags")?;
+        let mut stmt = self
+            .connection
+            .prepare("SELECT id, name, color, description FROM tags ORDER BY id")?;
         let tags = stmt
             .query_map([], |row| {
                 Ok(Tag {
                     id: row.get(0)?,
                     name: row.get(1)?,
+                    color: row.get(2)?,
+                    description: row.get(3)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<Tag>, _>>()?;
+
+        Ok(tags)
+    }
+
+    /// Returns the tags whose names start with `prefix` (compared case-insensitively for ASCII
+    /// letters), sorted by `order`. `limit` and `offset` select a page of the result.
+    pub fn get_tags_filtered(
+        &self,
+        prefix: Option<&str>,
+        order: TagOrder,
+        limit: Option<usize>,
+        offset: usize,
+    ) -> Result<Vec<Tag>> {
+        let pattern = prefix.map(|prefix| {
+            let mut pattern = String::with_capacity(prefix.len() + 1);
+            for c in prefix.chars() {
+                if matches!(c, '%' | '_' | '\\') {
+                    pattern.push('\\');
+                }
+                pattern.push(c);
+            }
+            pattern.push('%');
+            pattern
+        });
+        let limit = limit.map_or(-1, |limit| limit as i64);
+        let order = match order {
+            TagOrder::Name => "t.name",
+            TagOrder::Id => "t.id",
+            TagOrder::Count => {
+                "(SELECT COUNT(*) FROM media_tags mt WHERE mt.tag_id = t.id) DESC, t.name"
+            }
+        };
+
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT t.id, t.name, t.color, t.description FROM tags t
+             WHERE ?1 IS NULL OR t.name LIKE ?1 ESCAPE '\\'
+             ORDER BY {order}
+             LIMIT ?2 OFFSET ?3"
+        ))?;
+        let tags = stmt
+            .query_map((pattern, limit, offset as i64), |row| {
+                Ok(Tag {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    color: row.get(2)?,
+                    description: row.get(3)?,
                 })
             })?
             .collect::<std::result::Result<Vec<Tag>, _>>()?;
@@ -133,105 +828,2365 @@ impl MediaTag {
         Ok(tags)
     }
 
+    /// Returns all tags along with the number of media each is assigned to, sorted by `order` in
+    /// the database.
+    pub fn get_tags_ordered(&self, order: TagOrder) -> Result<Vec<(Tag, usize)>> {
+        let order = match order {
+            TagOrder::Name => "t.name",
+            TagOrder::Id => "t.id",
+            TagOrder::Count => "uses DESC, t.name",
+        };
+
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT t.id, t.name, t.color, t.description, COUNT(mt.media_id) AS uses
+             FROM tags t
+             LEFT JOIN media_tags mt ON t.id = mt.tag_id
+             GROUP BY t.id
+             ORDER BY {order}"
+        ))?;
+        let tags = stmt
+            .query_map([], |row| {
+                let tag = Tag {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    color: row.get(2)?,
+                    description: row.get(3)?,
+                };
+                Ok((tag, row.get(4)?))
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(tags)
+    }
+
     pub fn get_tag_id_map(&self) -> Result<HashMap<i64, String>> {
         let tags = self.get_tags()?;
         Ok(tags.into_iter().map(|t| (t.id, t.name)).collect())
     }
 
-    fn get_medium_id_or_insert(&self, path_str: &str) -> Result<i64> {
-        let id: i64 = self.connection.query_row(
-            "INSERT INTO media (path) VALUES (?1)
-             ON CONFLICT(path) DO UPDATE SET path=excluded.path
-             RETURNING id",
-            (path_str,),
-            |row| row.get(0),
-        )?;
-        Ok(id)
+    /// Returns the names in `names` that are neither a tag nor an alias, in the given order and
+    /// without duplicates. Looks up all names at once, so unknown tags can be reported before a
+    /// bulk operation starts.
+    pub fn missing_tags(&self, names: &[&str]) -> Result<Vec<String>> {
+        let normalized = names
+            .iter()
+            .map(|name| self.normalize_tag_name(name))
+            .collect::<Result<Vec<String>>>()?;
+        let mut lookup: Vec<&str> = names.to_vec();
+        lookup.extend(normalized.iter().map(String::as_str));
+        lookup.sort_unstable();
+        lookup.dedup();
+
+        let mut existing = HashSet::new();
+        for chunk in lookup.chunks(MAX_SQL_VARIABLES) {
+            let placeholders = (1..=chunk.len())
+                .map(|i| format!("?{i}"))
+                .collect::<Vec<_>>()
+                .join(",");
+            let mut stmt = self.connection.prepare(&format!(
+                "SELECT name FROM tags WHERE name IN ({placeholders})
+                 UNION SELECT alias FROM tag_aliases WHERE alias IN ({placeholders})"
+            ))?;
+            let mut rows = stmt.query(params_from_iter(chunk))?;
+            while let Some(row) = rows.next()? {
+                existing.insert(row.get::<_, String>(0)?);
+            }
+        }
+
+        let mut missing = Vec::new();
+        for (name, normalized) in names.iter().zip(&normalized) {
+            let exists = existing.contains(*name) || existing.contains(normalized);
+            if !exists && !missing.iter().any(|m| m == name) {
+                missing.push(name.to_string());
+            }
+        }
+        Ok(missing)
     }
 
-    pub fn add_tag<P: AsRef<Path>>(&self, path: P, tag_name: &str) -> Result<()> {
-        let path_str = self.resolve_path_to_db_string(path)?;
+    /// Returns the names of all tags that are not assigned to any medium.
+    pub fn unused_tags(&self) -> Result<Vec<String>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT name FROM tags
+             WHERE id NOT IN (SELECT tag_id FROM media_tags)
+             ORDER BY name",
+        )?;
+        let names = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<std::result::Result<Vec<String>, _>>()?;
 
-        let medium_id = self.get_medium_id_or_insert(&path_str)?;
+        Ok(names)
+    }
 
-        let tag_id: i64 = self
-            .connection
-            .query_row("SELECT id FROM tags WHERE name = ?1", (tag_name,), |row| {
-                row.get(0)
-            })
-            .optional()?
-            .ok_or_else(|| Error::TagDoesNotExist(tag_name.to_string()))?;
+    /// Deletes the tag named `name` along with its aliases and removes it from all media. Like
+    /// other lookups, `name` is written in the library's [`TagCase`] and may be an alias.
+    pub fn delete_tag(&self, name: &str) -> Result<()> {
+        let tag_id = self.get_tag_id(name)?;
+        self.connection
+            .execute("DELETE FROM tags WHERE id = ?1", (tag_id,))?;
+        self.refresh_tag_cache();
+        Ok(())
+    }
 
-        self.connection.execute(
-            "INSERT OR IGNORE INTO media_tags(media_id, tag_id) VALUES (?1, ?2)",
-            (medium_id, tag_id),
+    /// Returns what [`MediaTag::delete_tag`] would change without changing anything.
+    pub fn delete_tag_plan(&self, name: &str) -> Result<ChangePlan> {
+        let tag_id = self.get_tag_id(name)?;
+        let (name, associations): (String, usize) = self.connection.query_row(
+            "SELECT t.name, COUNT(mt.media_id) FROM tags t
+             LEFT JOIN media_tags mt ON t.id = mt.tag_id
+             WHERE t.id = ?1
+             GROUP BY t.id",
+            (tag_id,),
+            |row| Ok((row.get(0)?, row.get(1)?)),
         )?;
 
-        Ok(())
+        Ok(ChangePlan {
+            deleted_tags: vec![name],
+            removed_associations: associations,
+        })
     }
 
-    pub fn remove_tag<P: AsRef<Path>>(&self, path: P, tag_name: &str) -> Result<()> {
-        let path_str = self.resolve_path_to_db_string(path)?;
+    /// Deletes all tags that are not assigned to any medium and returns their names.
+    pub fn prune_tags(&self) -> Result<Vec<String>> {
+        let tx = self.connection.unchecked_transaction()?;
+        let mut pruned = {
+            let mut stmt = tx.prepare(
+                "DELETE FROM tags
+                 WHERE id NOT IN (SELECT tag_id FROM media_tags)
+                 RETURNING name",
+            )?;
+            stmt.query_map([], |row| row.get(0))?
+                .collect::<std::result::Result<Vec<String>, _>>()?
+        };
+        tx.commit()?;
+        self.refresh_tag_cache();
 
-        let medium_id: i64 = self
-            .connection
-            .query_row(
-                "SELECT id FROM media WHERE path = ?1",
-                (&path_str,),
-                |row| row.get(0),
-            )
-            .optional()?
-            .ok_or_else(|| Error::FileDoesNotExist(path_str))?;
+        pruned.sort_unstable();
+        Ok(pruned)
+    }
 
-        let tag_id: i64 = self
-            .connection
-            .query_row("SELECT id FROM tags WHERE name = ?1", (tag_name,), |row| {
-                row.get(0)
-            })
-            .optional()?
-            .ok_or_else(|| Error::TagDoesNotExist(tag_name.to_string()))?;
+    /// Returns the `(old, new)` names of the tags [`MediaTag::rename_tags_by_pattern`] would
+    /// rename, ordered by old name, without changing anything.
+    pub fn tag_renames_by_pattern(
+        &self,
+        find: &str,
+        replace: &str,
+    ) -> Result<Vec<(String, String)>> {
+        let find_parts: Vec<&str> = find.split('*').collect();
+        if replace.matches('*').count() > find_parts.len() - 1 {
+            return Err(Error::InvalidPattern(format!(
+                "\"{replace}\" contains more wildcards than \"{find}\""
+            )));
+        }
 
-        self.connection.execute(
-            "DELETE FROM media_tags WHERE media_id = ?1 AND tag_id = ?2",
-            (medium_id, tag_id),
-        )?;
+        let mut renames = Vec::new();
+        for tag in self.get_tags()? {
+            let Some(captures) = match_wildcards(&find_parts, &tag.name) else {
+                continue;
+            };
+            let mut captures = captures.into_iter();
+            let mut new_name = String::with_capacity(replace.len());
+            for (i, part) in replace.split('*').enumerate() {
+                if i > 0 {
+                    new_name.push_str(captures.next().unwrap_or_default());
+                }
+                new_name.push_str(part);
+            }
+            let new_name = self.normalize_tag_name(&new_name)?;
+            if new_name != tag.name {
+                validate_tag_name(&new_name)?;
+                renames.push((tag.name, new_name));
+            }
+        }
 
-        Ok(())
+        // A new name must neither be taken by a tag or alias that keeps its name nor be produced
+        // twice
+        let renamed: HashSet<&str> = renames.iter().map(|(old, _)| old.as_str()).collect();
+        let mut new_names = HashSet::new();
+        for (_, new_name) in &renames {
+            let taken = self.get_tag_id(new_name).is_ok() && !renamed.contains(new_name.as_str());
+            if taken || !new_names.insert(new_name.as_str()) {
+                return Err(Error::TagAlreadyExists(new_name.clone()));
+            }
+        }
+
+        Ok(renames)
     }
 
-    pub fn load_media_tag(&self) -> Result<MediaTags> {
-        let tag_id_map = self.get_tag_id_map()?;
+    /// Renames every tag matching `find`, where each `*` matches any (possibly empty) part of the
+    /// name. The `*`s in `replace` are substituted with the matched parts in order, so
+    /// `proj-*` -> `project/*` renames `proj-video` to `project/video`. Nothing is renamed if a new
+    /// name collides with another tag or alias. New names are written in the library's
+    /// [`TagCase`]. Returns the `(old, new)` names, ordered by old name.
+    pub fn rename_tags_by_pattern(
+        &self,
+        find: &str,
+        replace: &str,
+    ) -> Result<Vec<(String, String)>> {
+        let renames = self.tag_renames_by_pattern(find, replace)?;
 
-        let mut stmt = self.connection.prepare(
-            "SELECT m.id, m.path, GROUP_CONCAT(t.id, ',')
-             FROM media m
-             LEFT JOIN media_tags mt ON m.id = mt.media_id
-             LEFT JOIN tags t ON mt.tag_id = t.id
-             GROUP BY m.id",
-        )?;
+        let tx = self.connection.unchecked_transaction()?;
+        {
+            // Renaming in two steps allows chains like `a` -> `b`, `b` -> `c` despite the unique
+            // constraint on tag names
+            let mut stmt = tx.prepare("UPDATE tags SET name = ?2 WHERE name = ?1")?;
+            for (i, (old, _)) in renames.iter().enumerate() {
+                stmt.execute((old, format!("\0rename{i}")))?;
+            }
+            for (i, (_, new)) in renames.iter().enumerate() {
+                stmt.execute((format!("\0rename{i}"), new))?;
+            }
+        }
+        tx.commit()?;
+        self.refresh_tag_cache();
 
-        let media = stmt
-            .query_map([], |row| {
-                let path_string: String = row.get(1)?;
-                let path = self.root.join(path_string);
+        Ok(renames)
+    }
 
-                let tag_id_string: Option<String> = row.get(2)?;
-                let tags = match tag_id_string {
-                    Some(s) => s.split(',').filter_map(|x| x.parse::<i64>().ok()).collect(),
-                    None => Vec::new(),
-                };
+    /// Clusters tag names that are likely spelling variants of each other (`scifi`, `sci-fi`,
+    /// `Sci Fi`). Names are compared case-insensitively and ignoring everything except letters and
+    /// digits; two tags are considered similar if their normalized edit distance similarity
+    /// (between 0 and 1) is at least `threshold`. Only groups with more than one tag are returned.
+    pub fn find_similar_tags(&self, threshold: f64) -> Result<Vec<Vec<String>>> {
+        let mut names: Vec<String> = self.get_tags()?.into_iter().map(|t| t.name).collect();
+        names.sort_unstable();
+        let normalized: Vec<Vec<char>> = names
+            .iter()
+            .map(|name| {
+                name.chars()
+                    .filter(|c| c.is_alphanumeric())
+                    .flat_map(char::to_lowercase)
+                    .collect()
+            })
+            .collect();
 
-                Ok(Medium {
-                    id: row.get(0)?,
-                    path,
-                    tags,
-                })
-            })?
-            .collect::<std::result::Result<Vec<Medium>, _>>()?;
+        // Union-find over the tag indices
+        let mut parent: Vec<usize> = (0..names.len()).collect();
 
-        Ok(MediaTags {
-            media,
+        for i in 0..names.len() {
+            for j in i + 1..names.len() {
+                if similarity(&normalized[i], &normalized[j]) >= threshold {
+                    let (a, b) = (find_root(&mut parent, i), find_root(&mut parent, j));
+                    parent[b] = a;
+                }
+            }
+        }
+
+        let mut groups: HashMap<usize, Vec<String>> = HashMap::new();
+        for (i, name) in names.into_iter().enumerate() {
+            let root = find_root(&mut parent, i);
+            groups.entry(root).or_default().push(name);
+        }
+        let mut groups: Vec<Vec<String>> = groups
+            .into_values()
+            .filter(|group| group.len() > 1)
+            .collect();
+        groups.sort_unstable();
+
+        Ok(groups)
+    }
+
+    /// Returns `(stored, actual)` pairs of media whose stored path differs from the path on disk
+    /// only by case. Such paths work on case-insensitive file systems (macOS, Windows) but break
+    /// once the library is moved to a case-sensitive one. Files that cannot be found are skipped.
+    pub fn verify_path_case(&self) -> Result<Vec<(String, String)>> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT path FROM media ORDER BY path")?;
+        let paths = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let root = self.root()?;
+        let mut listings: HashMap<PathBuf, Vec<String>> = HashMap::new();
+        let mut mismatches = Vec::new();
+        'media: for stored in paths {
+            let mut dir = root.to_path_buf();
+            let mut actual = Vec::new();
+            for component in stored.split('/') {
+                let names = listings.entry(dir.clone()).or_insert_with(|| {
+                    fs::read_dir(&dir)
+                        .map(|entries| {
+                            entries
+                                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                                .collect()
+                        })
+                        .unwrap_or_default()
+                });
+                let lowercase = component.to_lowercase();
+                let name = names
+                    .iter()
+                    .find(|name| *name == component)
+                    .or_else(|| names.iter().find(|name| name.to_lowercase() == lowercase));
+                let Some(name) = name.cloned() else {
+                    continue 'media;
+                };
+                dir.push(&name);
+                actual.push(name);
+            }
+
+            let actual = actual.join("/");
+            if actual != stored {
+                mismatches.push((stored, actual));
+            }
+        }
+        Ok(mismatches)
+    }
+
+    /// Deletes duplicate assignments of the same tag to the same medium, which databases written
+    /// without a unique constraint on `media_tags` may contain, and adds a unique index so they
+    /// cannot reappear. Returns the number of deleted duplicates.
+    pub fn dedupe_associations(&self) -> Result<usize> {
+        let tx = self.connection.unchecked_transaction()?;
+
+        let removed = tx.execute(
+            "DELETE FROM media_tags
+             WHERE rowid NOT IN (SELECT MIN(rowid) FROM media_tags GROUP BY media_id, tag_id)",
+            [],
+        )?;
+
+        let mut has_unique_index = false;
+        {
+            let mut stmt = tx.prepare(
+                "SELECT il.name FROM pragma_index_list('media_tags') il WHERE il.\"unique\"",
+            )?;
+            let indexes = stmt
+                .query_map([], |row| row.get::<_, String>(0))?
+                .collect::<std::result::Result<Vec<_>, _>>()?;
+            for index in indexes {
+                let mut stmt =
+                    tx.prepare("SELECT name FROM pragma_index_info(?1) ORDER BY seqno")?;
+                let columns = stmt
+                    .query_map((&index,), |row| row.get::<_, String>(0))?
+                    .collect::<std::result::Result<Vec<_>, _>>()?;
+                has_unique_index |= columns == ["media_id", "tag_id"];
+            }
+        }
+        if !has_unique_index {
+            tx.execute(
+                "CREATE UNIQUE INDEX media_tags_unique ON media_tags(media_id, tag_id)",
+                [],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(removed)
+    }
+
+    /// Returns the number of tracked media, tagged or not.
+    pub fn media_count(&self) -> Result<i64> {
+        let count = self
+            .connection
+            .query_row("SELECT COUNT(*) FROM media", [], |row| row.get(0))?;
+        Ok(count)
+    }
+
+    /// Returns the number of media that have at least one tag.
+    pub fn tagged_media_count(&self) -> Result<i64> {
+        let count = self.connection.query_row(
+            "SELECT COUNT(DISTINCT media_id) FROM media_tags",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(count)
+    }
+
+    /// Returns every tag name with the number of media it is assigned to, most used first and
+    /// then by name. Unused tags are included with a count of 0.
+    pub fn get_tag_counts(&self) -> Result<Vec<(String, i64)>> {
+        let tags = self.get_tags_ordered(TagOrder::Count)?;
+        Ok(tags
+            .into_iter()
+            .map(|(tag, count)| (tag.name, count as i64))
+            .collect())
+    }
+
+    /// Returns how many media carry the tag `name`, when it was first and last assigned and a
+    /// sample of the media. Fails with [`Error::TagDoesNotExist`] if there is no such tag.
+    pub fn tag_detail(&self, name: &str) -> Result<TagDetail> {
+        let tag_id = self.get_tag_id(name)?;
+
+        let tag = self.connection.query_row(
+            "SELECT id, name, color, description FROM tags WHERE id = ?1",
+            (tag_id,),
+            |row| {
+                Ok(Tag {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    color: row.get(2)?,
+                    description: row.get(3)?,
+                })
+            },
+        )?;
+        let (files, first_used, last_used) = self.connection.query_row(
+            "SELECT COUNT(*), MIN(created_at), MAX(created_at) FROM media_tags WHERE tag_id = ?1",
+            (tag_id,),
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )?;
+
+        let mut stmt = self.connection.prepare(
+            "SELECT m.path
+             FROM media_tags mt
+             JOIN media m ON m.id = mt.media_id
+             WHERE mt.tag_id = ?1
+             ORDER BY mt.created_at DESC, m.path
+             LIMIT ?2",
+        )?;
+        let root = self.output_root();
+        let sample = stmt
+            .query_map((tag_id, TAG_DETAIL_SAMPLE_SIZE), |row| {
+                Ok(from_db_string(root, &row.get::<_, String>(0)?))
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(TagDetail {
+            tag,
+            files,
+            first_used,
+            last_used,
+            sample,
+        })
+    }
+
+    /// Returns the string under which the file at the absolute `path` (like [`Medium::path`]) is
+    /// stored in the database.
+    pub fn stored_path<P: AsRef<Path>>(&self, path: P) -> Result<String> {
+        let rel_path = self.strip_root(path.as_ref())?;
+
+        to_db_string(rel_path)
+    }
+
+    fn get_medium_id_or_insert(&self, path_str: &str) -> Result<i64> {
+        let mtime = file_mtime(&from_db_string(self.media_root(), path_str));
+        let id: i64 = self.connection.query_row(
+            "INSERT INTO media (path, mtime) VALUES (?1, ?2)
+             ON CONFLICT(path) DO UPDATE SET mtime=COALESCE(excluded.mtime, mtime)
+             RETURNING id",
+            (path_str, mtime),
+            |row| row.get(0),
+        )?;
+        Ok(id)
+    }
+
+    /// Inserts all given media in as few statements as possible and returns their ids in the same
+    /// order as `paths`. Media that are already tracked keep their id.
+    pub fn bulk_insert_media<P: AsRef<Path>>(&self, paths: &[P]) -> Result<Vec<i64>> {
+        let path_strs = paths
+            .iter()
+            .map(|path| self.resolve_path_to_db_string(path))
+            .collect::<Result<Vec<String>>>()?;
+
+        let ids = self.with_retries(|| {
+            let tx = self.connection.unchecked_transaction()?;
+            let ids =
+                insert_media_paths(&tx, self.media_root(), path_strs.iter().map(String::as_str))?;
+            tx.commit()?;
+            Ok(ids)
+        })?;
+
+        Ok(path_strs.iter().map(|path_str| ids[path_str]).collect())
+    }
+
+    /// Inserts `(media_id, tag_id)` associations in batches inside a single transaction. Existing
+    /// associations are left untouched. About 1.3 times faster than one statement per
+    /// association, as measured by `examples/bench_bulk_insert.rs`.
+    pub fn bulk_insert_associations(&self, pairs: &[(i64, i64)]) -> Result<()> {
+        self.with_retries(|| {
+            let tx = self.connection.unchecked_transaction()?;
+            for chunk in pairs.chunks(MAX_SQL_VARIABLES / 2) {
+                let placeholders = vec!["(?, ?, unixepoch())"; chunk.len()].join(",");
+                let params = chunk
+                    .iter()
+                    .flat_map(|(media_id, tag_id)| [media_id, tag_id]);
+                // All chunks but the last have the same size, so the statement is parsed once
+                tx.prepare_cached(&format!(
+                    "INSERT OR IGNORE INTO media_tags(media_id, tag_id, created_at) VALUES {placeholders}"
+                ))?
+                .execute(params_from_iter(params))?;
+            }
+            tx.commit()?;
+            Ok(())
+        })
+    }
+
+    /// Looks up a tag by name or alias, written in the library's [`TagCase`] or exactly as stored
+    /// (for tags created before the casing was set).
+    fn get_tag_id(&self, tag_name: &str) -> Result<i64> {
+        let normalized = self.normalize_tag_name(tag_name)?;
+        self.with_tag_ids(|ids| ids.get(&normalized).or_else(|| ids.get(tag_name)).copied())?
+            .ok_or_else(|| Error::TagDoesNotExist(tag_name.to_string()))
+    }
+
+    /// Calls `f` with the cached map of tag names and aliases to tag ids, loading it first if
+    /// necessary.
+    fn with_tag_ids<T>(&self, f: impl FnOnce(&HashMap<String, i64>) -> T) -> Result<T> {
+        let mut tag_ids = self.tag_ids.borrow_mut();
+        if tag_ids.is_none() {
+            // Tag names come last so they win over aliases of the same name
+            let mut stmt = self.connection.prepare(
+                "SELECT alias, tag_id FROM tag_aliases UNION ALL SELECT name, id FROM tags",
+            )?;
+            let ids = stmt
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<std::result::Result<HashMap<String, i64>, _>>()?;
+            *tag_ids = Some(ids);
+        }
+
+        Ok(f(tag_ids.get_or_insert_default()))
+    }
+
+    /// Returns the ids of the tags named `tag_name` (or having it as an alias), which with
+    /// `ignore_case` may be several. Unknown names resolve to no tags.
+    fn find_tag_ids(&self, tag_name: &str, ignore_case: bool) -> Result<Vec<i64>> {
+        if !ignore_case {
+            return Ok(self.get_tag_id(tag_name).into_iter().collect());
+        }
+
+        let tag_name = tag_name.to_lowercase();
+        self.with_tag_ids(|ids| {
+            let mut matching: Vec<i64> = ids
+                .iter()
+                .filter(|(name, _)| name.to_lowercase() == tag_name)
+                .map(|(_, id)| *id)
+                .collect();
+            matching.sort_unstable();
+            matching.dedup();
+            matching
+        })
+    }
+
+    /// Looks up the id of a tracked file. Errors report `path` the way the caller passed it
+    /// rather than its stored form.
+    fn get_medium_id<P: AsRef<Path>>(&self, path: P) -> Result<i64> {
+        let path = path.as_ref();
+        let path_str = self.resolve_path_to_db_string(path)?;
+
+        self.connection
+            .query_row(
+                "SELECT id FROM media WHERE path = ?1",
+                (&path_str,),
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| Error::FileDoesNotExist(path.display().to_string()))
+    }
+
+    /// Adds a file to the database without tagging it. Returns the id of its medium, which may
+    /// already have been tracked.
+    pub fn track<P: AsRef<Path>>(&self, path: P) -> Result<i64> {
+        let path_str = self.resolve_path_to_db_string(path)?;
+
+        self.get_medium_id_or_insert(&path_str)
+    }
+
+    /// Tracks a file like [`MediaTag::track`] and adds all of `tag_names` to it in one
+    /// transaction, so a batch tool needs no separate [`MediaTag::add_tag`] call per tag. Returns
+    /// the id of the medium. Fails with [`Error::TagDoesNotExist`] on unknown tags, leaving the
+    /// file untouched.
+    pub fn track_and_tag<P: AsRef<Path>>(&self, path: P, tag_names: &[&str]) -> Result<i64> {
+        self.track_and_tag_with(path, tag_names, MissingTag::Error)
+    }
+
+    /// Like [`MediaTag::track_and_tag`], see [`MediaTag::add_tag_with`] for `missing`.
+    pub fn track_and_tag_with<P: AsRef<Path>>(
+        &self,
+        path: P,
+        tag_names: &[&str],
+        missing: MissingTag,
+    ) -> Result<i64> {
+        let path_str = self.resolve_path_to_db_string(path)?;
+
+        self.with_retries(|| {
+            let tx = self.connection.unchecked_transaction()?;
+            let medium_id = self.get_medium_id_or_insert(&path_str)?;
+            for tag_name in tag_names {
+                if let Err(e) = self.add_tag_to_medium_with(medium_id, tag_name, missing) {
+                    // Tags created before the error are rolled back
+                    drop(tx);
+                    self.refresh_tag_cache();
+                    return Err(e);
+                }
+            }
+            tx.commit()?;
+            Ok(medium_id)
+        })
+    }
+
+    /// Removes a file from the database along with its tags, note and rating. The file itself may
+    /// already be deleted, only its directory has to exist. Fails with
+    /// [`Error::FileDoesNotExist`] if the file is not tracked.
+    pub fn forget_medium<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let path_str = self.resolve_missing_path_to_db_string(path)?;
+
+        // Tag assignments are removed by `ON DELETE CASCADE`
+        let deleted = self
+            .connection
+            .execute("DELETE FROM media WHERE path = ?1", (&path_str,))?;
+        if deleted == 0 {
+            return Err(Error::FileDoesNotExist(path.display().to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Adds a tag to a file, tracking the file if necessary. Returns `false` if the file already
+    /// had the tag.
+    pub fn add_tag<P: AsRef<Path>>(&self, path: P, tag_name: &str) -> Result<bool> {
+        self.add_tag_with(path, tag_name, MissingTag::Error)
+    }
+
+    /// Like [`MediaTag::add_tag`] but creates the tag first if it does not exist yet.
+    pub fn add_tag_creating<P: AsRef<Path>>(&self, path: P, tag_name: &str) -> Result<bool> {
+        self.add_tag_with(path, tag_name, MissingTag::Create)
+    }
+
+    /// Like [`MediaTag::add_tag`], with `missing` deciding whether a tag that does not exist is
+    /// created or an error.
+    pub fn add_tag_with<P: AsRef<Path>>(
+        &self,
+        path: P,
+        tag_name: &str,
+        missing: MissingTag,
+    ) -> Result<bool> {
+        let path_str = self.resolve_path_to_db_string(path)?;
+
+        self.with_retries(|| {
+            let medium_id = self.get_medium_id_or_insert(&path_str)?;
+            self.add_tag_to_medium_with(medium_id, tag_name, missing)
+        })
+    }
+
+    /// Like [`MediaTag::add_tag`] but for an already tracked medium, which does not need to exist
+    /// on disk.
+    pub fn add_tag_to_medium(&self, medium_id: i64, tag_name: &str) -> Result<bool> {
+        self.add_tag_to_medium_with(medium_id, tag_name, MissingTag::Error)
+    }
+
+    /// Like [`MediaTag::add_tag_to_medium`], see [`MediaTag::add_tag_with`] for `missing`.
+    pub fn add_tag_to_medium_with(
+        &self,
+        medium_id: i64,
+        tag_name: &str,
+        missing: MissingTag,
+    ) -> Result<bool> {
+        if missing == MissingTag::Create && self.get_tag_id(tag_name).is_err() {
+            self.create_tag(tag_name)?;
+        }
+        let tag_id = self.get_tag_id(tag_name)?;
+
+        self.add_tag_id_to_medium(medium_id, tag_id)
+    }
+
+    /// Like [`MediaTag::add_tag`] but takes the id of the tag instead of its name.
+    pub fn add_tag_id_by_medium<P: AsRef<Path>>(&self, path: P, tag_id: i64) -> Result<bool> {
+        let path_str = self.resolve_path_to_db_string(path)?;
+
+        let medium_id = self.get_medium_id_or_insert(&path_str)?;
+
+        self.add_tag_id_to_medium(medium_id, tag_id)
+    }
+
+    /// Adds the tag with id `tag_id` to an already tracked medium. Fails with
+    /// [`Error::TagIdDoesNotExist`] if there is no such tag.
+    pub fn add_tag_id_to_medium(&self, medium_id: i64, tag_id: i64) -> Result<bool> {
+        let exists: bool = self.connection.query_row(
+            "SELECT EXISTS(SELECT 1 FROM tags WHERE id = ?1)",
+            (tag_id,),
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Err(Error::TagIdDoesNotExist(tag_id));
+        }
+
+        let added = self.connection.execute(
+            "INSERT OR IGNORE INTO media_tags(media_id, tag_id, created_at)
+             VALUES (?1, ?2, unixepoch())",
+            (medium_id, tag_id),
+        )?;
+
+        Ok(added > 0)
+    }
+
+    /// Adds all tags of the tracked file `from` to `to`, tracking `to` if necessary. Returns how
+    /// many tags `to` did not have yet.
+    pub fn copy_tags<P: AsRef<Path>>(&self, from: P, to: P) -> Result<usize> {
+        let from_id = self.get_medium_id(from)?;
+        let to_str = self.resolve_path_to_db_string(to)?;
+        let to_id = self.get_medium_id_or_insert(&to_str)?;
+
+        let added = self.connection.execute(
+            "INSERT OR IGNORE INTO media_tags(media_id, tag_id, created_at)
+             SELECT ?2, tag_id, unixepoch() FROM media_tags WHERE media_id = ?1",
+            (from_id, to_id),
+        )?;
+        Ok(added)
+    }
+
+    /// Removes a tag from a tracked file. Returns `false` if the file did not have the tag.
+    pub fn remove_tag<P: AsRef<Path>>(&self, path: P, tag_name: &str) -> Result<bool> {
+        let medium_id = self.get_medium_id(path)?;
+
+        self.with_retries(|| self.remove_tag_from_medium(medium_id, tag_name))
+    }
+
+    /// Like [`MediaTag::remove_tag`] but for an already tracked medium, which does not need to
+    /// exist on disk. Returns `false` if the medium did not have the tag.
+    pub fn remove_tag_from_medium(&self, medium_id: i64, tag_name: &str) -> Result<bool> {
+        let tag_id = self.get_tag_id(tag_name)?;
+
+        let removed = self.connection.execute(
+            "DELETE FROM media_tags WHERE media_id = ?1 AND tag_id = ?2",
+            (medium_id, tag_id),
+        )?;
+
+        Ok(removed > 0)
+    }
+
+    /// Removes every tag in `tags` from every file in `paths` in a single transaction. Pairs that
+    /// are not associated and files that are not tracked are skipped. Unknown tags are skipped as
+    /// well unless `strict` is set, in which case nothing is removed and
+    /// [`Error::TagDoesNotExist`] is returned. Returns the number of removed associations.
+    pub fn remove_tags_bulk(
+        &self,
+        paths: &[PathBuf],
+        tags: &[String],
+        strict: bool,
+    ) -> Result<usize> {
+        let mut tag_ids = Vec::with_capacity(tags.len());
+        for tag in tags {
+            match self.get_tag_id(tag) {
+                Ok(id) => tag_ids.push(id),
+                Err(Error::TagDoesNotExist(_)) if !strict => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let path_strs = paths
+            .iter()
+            .map(|path| self.resolve_path_to_db_string(path))
+            .collect::<Result<Vec<String>>>()?;
+
+        self.with_retries(|| {
+            let tx = self.connection.unchecked_transaction()?;
+            let mut removed = 0;
+            {
+                let mut stmt = tx.prepare(
+                    "DELETE FROM media_tags
+                     WHERE media_id = (SELECT id FROM media WHERE path = ?1) AND tag_id = ?2",
+                )?;
+                for path_str in &path_strs {
+                    for tag_id in &tag_ids {
+                        removed += stmt.execute((path_str, tag_id))?;
+                    }
+                }
+            }
+            tx.commit()?;
+            Ok(removed)
+        })
+    }
+
+    /// Adds every tag in `tags` to all media matching `query` in a single transaction. `missing`
+    /// decides whether tags that do not exist are created or fail the whole operation. Returns the
+    /// number of added associations.
+    pub fn add_tags_matching(
+        &self,
+        query: &SearchQuery,
+        tags: &[&str],
+        missing: MissingTag,
+    ) -> Result<usize> {
+        let media = self.search(query)?;
+
+        let tx = self.connection.unchecked_transaction()?;
+        let result = (|| {
+            let mut tag_ids = Vec::with_capacity(tags.len());
+            for tag in tags {
+                if missing == MissingTag::Create && self.get_tag_id(tag).is_err() {
+                    self.create_tag(tag)?;
+                }
+                tag_ids.push(self.get_tag_id(tag)?);
+            }
+
+            let mut stmt = tx.prepare(
+                "INSERT OR IGNORE INTO media_tags(media_id, tag_id, created_at)
+                 VALUES (?1, ?2, unixepoch())",
+            )?;
+            let mut added = 0;
+            for medium in &media {
+                for tag_id in &tag_ids {
+                    added += stmt.execute((medium.id, tag_id))?;
+                }
+            }
+            Ok(added)
+        })();
+        match result {
+            Ok(added) => {
+                tx.commit()?;
+                Ok(added)
+            }
+            Err(e) => {
+                // Tags created before the failure are rolled back
+                self.refresh_tag_cache();
+                Err(e)
+            }
+        }
+    }
+
+    /// Removes every tag in `tags` from all media matching `query` in a single transaction. Fails
+    /// with [`Error::TagDoesNotExist`] before removing anything if one of the tags does not exist.
+    /// Returns the number of removed associations.
+    pub fn remove_tags_matching(&self, query: &SearchQuery, tags: &[&str]) -> Result<usize> {
+        let tag_ids = tags
+            .iter()
+            .map(|tag| self.get_tag_id(tag))
+            .collect::<Result<Vec<i64>>>()?;
+        let media = self.search(query)?;
+
+        let tx = self.connection.unchecked_transaction()?;
+        let mut removed = 0;
+        {
+            let mut stmt =
+                tx.prepare("DELETE FROM media_tags WHERE media_id = ?1 AND tag_id = ?2")?;
+            for medium in &media {
+                for tag_id in &tag_ids {
+                    removed += stmt.execute((medium.id, tag_id))?;
+                }
+            }
+        }
+        tx.commit()?;
+
+        Ok(removed)
+    }
+
+    /// Counts the assignments of the tags named `tag_names` to the media with `medium_ids`.
+    /// Returns the number of assignments and the number of media they belong to, i.e. what
+    /// removing these tags from these media would delete.
+    pub fn count_associations(
+        &self,
+        medium_ids: &[i64],
+        tag_names: &[String],
+    ) -> Result<(usize, usize)> {
+        let mut medium_ids = medium_ids.to_vec();
+        medium_ids.sort_unstable();
+        medium_ids.dedup();
+        let tag_ids: Vec<i64> = tag_names
+            .iter()
+            .filter_map(|name| self.get_tag_id(name).ok())
+            .collect();
+        if tag_ids.is_empty() {
+            return Ok((0, 0));
+        }
+
+        let (mut associations, mut media) = (0, 0);
+        let tag_placeholders = vec!["?"; tag_ids.len()].join(",");
+        for chunk in medium_ids.chunks(MAX_SQL_VARIABLES.saturating_sub(tag_ids.len()).max(1)) {
+            let placeholders = vec!["?"; chunk.len()].join(",");
+            let (chunk_associations, chunk_media): (usize, usize) = self.connection.query_row(
+                &format!(
+                    "SELECT COUNT(*), COUNT(DISTINCT media_id) FROM media_tags
+                     WHERE media_id IN ({placeholders}) AND tag_id IN ({tag_placeholders})"
+                ),
+                params_from_iter(chunk.iter().chain(&tag_ids)),
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+            associations += chunk_associations;
+            media += chunk_media;
+        }
+        Ok((associations, media))
+    }
+
+    /// Looks up a tracked medium by the file name of `path` alone. This is used to find a medium
+    /// whose file has been moved since it was tagged. Fails if no medium or more than one medium
+    /// has that file name.
+    pub fn find_medium_by_file_name<P: AsRef<Path>>(&self, path: P) -> Result<Medium> {
+        let path = path.as_ref();
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| Error::FileDoesNotExist(path.display().to_string()))?;
+
+        let mut candidates: Vec<Medium> = self
+            .load_media_tag()?
+            .media
+            .into_iter()
+            .filter(|medium| medium.path.file_name() == Some(file_name))
+            .collect();
+
+        match candidates.len() {
+            0 => Err(Error::FileDoesNotExist(path.display().to_string())),
+            1 => Ok(candidates.remove(0)),
+            _ => Err(Error::AmbiguousFileName(
+                path.display().to_string(),
+                candidates.into_iter().map(|m| m.path).collect(),
+            )),
+        }
+    }
+
+    /// Returns the number of tags of a tracked file without loading them.
+    pub fn tag_count<P: AsRef<Path>>(&self, path: P) -> Result<usize> {
+        let path = path.as_ref();
+        let path_str = self.resolve_path_to_db_string(path)?;
+
+        self.connection
+            .query_row(
+                "SELECT COUNT(mt.tag_id) FROM media m
+                 LEFT JOIN media_tags mt ON m.id = mt.media_id
+                 WHERE m.path = ?1
+                 GROUP BY m.id",
+                (&path_str,),
+                |row| row.get(0),
+            )
+            .optional()?
+            .ok_or_else(|| Error::FileDoesNotExist(path.display().to_string()))
+    }
+
+    /// Changes the stored path of the medium at `from` to `to`, keeping its tags and note. Only the
+    /// database is updated; neither file has to exist, but their directories do.
+    pub fn move_medium<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> Result<()> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        let from_str = self.resolve_missing_path_to_db_string(from)?;
+        let to_str = self.resolve_missing_path_to_db_string(to)?;
+
+        let already_tracked: bool = self.connection.query_row(
+            "SELECT EXISTS(SELECT 1 FROM media WHERE path = ?1)",
+            (&to_str,),
+            |row| row.get(0),
+        )?;
+        if already_tracked {
+            return Err(Error::FileAlreadyTracked(to.display().to_string()));
+        }
+
+        let updated = self.connection.execute(
+            "UPDATE media SET path = ?2 WHERE path = ?1",
+            (&from_str, &to_str),
+        )?;
+        if updated == 0 {
+            return Err(Error::FileDoesNotExist(from.display().to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Changes the stored paths of all media inside the directory `old_prefix` to the directory
+    /// `new_prefix`, e.g. after moving a folder, keeping their tags. Both are relative to the
+    /// library root as stored in the database (`photos/2023`); an empty `new_prefix` is the root.
+    /// Returns the number of moved media.
+    ///
+    /// Nothing is changed if a moved medium would land on a path that is already tracked
+    /// ([`Error::FileAlreadyTracked`]), or if `new_prefix` lies inside `old_prefix`
+    /// ([`Error::InvalidPattern`]).
+    pub fn relocate_prefix(&self, old_prefix: &str, new_prefix: &str) -> Result<usize> {
+        let old_prefix = old_prefix.trim_matches('/');
+        let new_prefix = new_prefix.trim_matches('/');
+        if old_prefix.is_empty() {
+            return Err(Error::InvalidPattern(
+                "the directory to relocate must not be the library root".to_string(),
+            ));
+        }
+        if new_prefix == old_prefix || new_prefix.starts_with(&format!("{old_prefix}/")) {
+            return Err(Error::InvalidPattern(format!(
+                "cannot move {old_prefix} into itself"
+            )));
+        }
+        let old_prefix = format!("{old_prefix}/");
+        let new_prefix = if new_prefix.is_empty() {
+            String::new()
+        } else {
+            format!("{new_prefix}/")
+        };
+
+        let tx = self.connection.unchecked_transaction()?;
+        let collision: Option<String> = tx
+            .query_row(
+                "SELECT ?2 || substr(m.path, length(?1) + 1) AS moved
+                 FROM media m
+                 WHERE substr(m.path, 1, length(?1)) = ?1
+                   AND EXISTS(SELECT 1 FROM media o WHERE o.path = moved)
+                 LIMIT 1",
+                (&old_prefix, &new_prefix),
+                |row| row.get(0),
+            )
+            .optional()?;
+        if let Some(path) = collision {
+            return Err(Error::FileAlreadyTracked(path));
+        }
+
+        let moved = tx.execute(
+            "UPDATE media SET path = ?2 || substr(path, length(?1) + 1)
+             WHERE substr(path, 1, length(?1)) = ?1",
+            (&old_prefix, &new_prefix),
+        )?;
+        tx.commit()?;
+
+        Ok(moved)
+    }
+
+    /// Loads the tracked medium at `path` along with its tags.
+    pub fn get_medium_by_path<P: AsRef<Path>>(&self, path: P) -> Result<Medium> {
+        let path = path.as_ref();
+        let path_str = self.resolve_path_to_db_string(path)?;
+
+        self.load_media_tag_where("WHERE m.path = ?1", "", (&path_str,))?
+            .media
+            .pop()
+            .ok_or_else(|| Error::FileDoesNotExist(path.display().to_string()))
+    }
+
+    /// Looks up the media stored at `paths` with one query per chunk of paths instead of one query
+    /// per path. The result is aligned with `paths`; untracked paths yield `None`. Like
+    /// [`MediaTag::move_medium`], only the directories of the paths have to exist.
+    pub fn get_media_by_paths(&self, paths: &[PathBuf]) -> Result<Vec<Option<Medium>>> {
+        let path_strs = paths
+            .iter()
+            .map(|path| self.resolve_missing_path_to_db_string(path))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut found = HashMap::with_capacity(path_strs.len());
+        for chunk in path_strs.chunks(MAX_SQL_VARIABLES) {
+            let placeholders = vec!["?"; chunk.len()].join(",");
+            let media = self
+                .load_media_tag_where(
+                    &format!("WHERE m.path IN ({placeholders})"),
+                    "",
+                    params_from_iter(chunk),
+                )?
+                .media;
+            found.extend(
+                media
+                    .into_iter()
+                    .map(|medium| (medium.relative.clone(), medium)),
+            );
+        }
+
+        Ok(path_strs
+            .iter()
+            .map(|path_str| found.get(&from_db_string(Path::new(""), path_str)).cloned())
+            .collect())
+    }
+
+    /// Attaches a free-text note to a file, tracking it if necessary. `None` removes the note.
+    pub fn set_note<P: AsRef<Path>>(&self, path: P, note: Option<&str>) -> Result<()> {
+        let path_str = self.resolve_path_to_db_string(path)?;
+
+        let medium_id = self.get_medium_id_or_insert(&path_str)?;
+
+        self.connection.execute(
+            "UPDATE media SET note = ?1 WHERE id = ?2",
+            (note, medium_id),
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_note<P: AsRef<Path>>(&self, path: P) -> Result<Option<String>> {
+        let medium_id = self.get_medium_id(path)?;
+
+        let note = self.connection.query_row(
+            "SELECT note FROM media WHERE id = ?1",
+            (medium_id,),
+            |row| row.get(0),
+        )?;
+        Ok(note)
+    }
+
+    /// Rates a file from 0 to [`MAX_RATING`], tracking it if necessary. `None` removes the rating.
+    pub fn set_rating<P: AsRef<Path>>(&self, path: P, rating: Option<u8>) -> Result<()> {
+        if let Some(rating) = rating.filter(|rating| *rating > MAX_RATING) {
+            return Err(Error::InvalidRating(rating));
+        }
+        let path_str = self.resolve_path_to_db_string(path)?;
+
+        let medium_id = self.get_medium_id_or_insert(&path_str)?;
+
+        self.connection.execute(
+            "UPDATE media SET rating = ?1 WHERE id = ?2",
+            (rating, medium_id),
+        )?;
+
+        Ok(())
+    }
+
+    /// Returns the media whose recorded modification time lies between `from` (inclusive) and `to`
+    /// (exclusive), both in seconds since the Unix epoch, oldest first. Media without a recorded
+    /// modification time are never returned.
+    pub fn media_modified_between(&self, from: i64, to: i64) -> Result<Vec<Medium>> {
+        let media_tag_data = self.load_media_tag_where(
+            "WHERE m.mtime >= ?1 AND m.mtime < ?2",
+            "ORDER BY m.mtime, m.path",
+            (from, to),
+        )?;
+        Ok(media_tag_data.media)
+    }
+
+    /// Records the current modification time of every tracked file, or only of those without one
+    /// (e.g. tracked before modification times were recorded) unless `all` is set. Files that
+    /// cannot be read are reported to `on_error` and skipped. Returns the number of updated files.
+    pub fn update_mtimes<F: FnMut(&Path, Error)>(
+        &self,
+        all: bool,
+        mut on_error: F,
+    ) -> Result<usize> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT id, path FROM media WHERE ?1 OR mtime IS NULL")?;
+        let media = stmt
+            .query_map((all,), |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        let root = self.root()?;
+        let tx = self.connection.unchecked_transaction()?;
+        let mut updated = 0;
+        {
+            let mut update = tx.prepare("UPDATE media SET mtime = ?1 WHERE id = ?2")?;
+            for (id, path_str) in media {
+                let path = from_db_string(root, &path_str);
+                match fs::metadata(&path).and_then(|metadata| metadata.modified()) {
+                    Ok(modified) => {
+                        update.execute((unix_seconds(modified), id))?;
+                        updated += 1;
+                    }
+                    Err(e) => on_error(&path, e.into()),
+                }
+            }
+        }
+        tx.commit()?;
+
+        Ok(updated)
+    }
+
+    /// Returns the tags of a medium along with the unix timestamp at which each was added, oldest
+    /// first. Associations created before timestamps were recorded report `0`.
+    pub fn medium_tag_history<P: AsRef<Path>>(&self, path: P) -> Result<Vec<(String, i64)>> {
+        let medium_id = self.get_medium_id(path)?;
+
+        let mut stmt = self.connection.prepare(
+            "SELECT t.name, COALESCE(mt.created_at, 0) AS created_at
+             FROM media_tags mt
+             JOIN tags t ON mt.tag_id = t.id
+             WHERE mt.media_id = ?1
+             ORDER BY created_at, t.name",
+        )?;
+        let history = stmt
+            .query_map((medium_id,), |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<std::result::Result<Vec<(String, i64)>, _>>()?;
+
+        Ok(history)
+    }
+
+    pub fn load_media_tag(&self) -> Result<MediaTags> {
+        self.load_media_tag_where("", "", [])
+    }
+
+    /// Like [`MediaTag::load_media_tag`] but only returns media that have all (or with `any` at
+    /// least one) of `include_tag_ids` and none of `exclude_tag_ids`. The filtering happens in
+    /// SQL, which avoids materializing the whole library: a selective query over 50000 media runs
+    /// about 12 times faster, as measured by `examples/bench_filtered_load.rs`.
+    pub fn load_media_tag_filtered(
+        &self,
+        include_tag_ids: &[i64],
+        exclude_tag_ids: &[i64],
+        any: bool,
+    ) -> Result<MediaTags> {
+        let mut conditions = Vec::new();
+        if !include_tag_ids.is_empty() {
+            let placeholders = vec!["?"; include_tag_ids.len()].join(",");
+            if any {
+                conditions.push(format!(
+                    "m.id IN (SELECT media_id FROM media_tags WHERE tag_id IN ({placeholders}))"
+                ));
+            } else {
+                conditions.push(format!(
+                    "m.id IN (SELECT media_id FROM media_tags WHERE tag_id IN ({placeholders})
+                              GROUP BY media_id HAVING COUNT(DISTINCT tag_id) = {})",
+                    include_tag_ids.len()
+                ));
+            }
+        }
+        if !exclude_tag_ids.is_empty() {
+            let placeholders = vec!["?"; exclude_tag_ids.len()].join(",");
+            conditions.push(format!(
+                "m.id NOT IN (SELECT media_id FROM media_tags WHERE tag_id IN ({placeholders}))"
+            ));
+        }
+
+        let filter = if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        };
+        self.load_media_tag_where(
+            &filter,
+            "",
+            params_from_iter(include_tag_ids.iter().chain(exclude_tag_ids)),
+        )
+    }
+
+    /// Returns the media that have all (or with `any` at least one) of the tags in `include` and
+    /// none of those in `exclude`. Tags can be given by name or alias. A medium
+    /// cannot have a tag that does not exist, so an unknown tag in `include` matches nothing unless
+    /// `any` is set, and unknown tags in `exclude` are ignored.
+    pub fn query(&self, include: &[&str], exclude: &[&str], any: bool) -> Result<Vec<Medium>> {
+        self.query_tags(include, exclude, any, false)
+    }
+
+    /// Like [`MediaTag::query`] but compares tag names case-insensitively. Fails with
+    /// [`Error::AmbiguousTagName`] if a tag in `include` matches several tags and `any` is not
+    /// set.
+    pub fn query_ignore_case(
+        &self,
+        include: &[&str],
+        exclude: &[&str],
+        any: bool,
+    ) -> Result<Vec<Medium>> {
+        self.query_tags(include, exclude, any, true)
+    }
+
+    fn query_tags(
+        &self,
+        include: &[&str],
+        exclude: &[&str],
+        any: bool,
+        ignore_case: bool,
+    ) -> Result<Vec<Medium>> {
+        let mut include_ids = Vec::new();
+        for tag_name in include {
+            let ids = self.find_tag_ids(tag_name, ignore_case)?;
+            if !any {
+                match ids.len() {
+                    0 => return Ok(Vec::new()),
+                    1 => {}
+                    _ => return Err(Error::AmbiguousTagName(tag_name.to_string())),
+                }
+            }
+            include_ids.extend(ids);
+        }
+        if any && !include.is_empty() && include_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+        // The same tag may be given twice, e.g. by name and alias
+        include_ids.sort_unstable();
+        include_ids.dedup();
+
+        let mut exclude_ids = Vec::new();
+        for tag_name in exclude {
+            exclude_ids.extend(self.find_tag_ids(tag_name, ignore_case)?);
+        }
+
+        Ok(self
+            .load_media_tag_filtered(&include_ids, &exclude_ids, any)?
+            .media)
+    }
+
+    /// Returns the media whose tags are exactly `names`: they have all of them and no others. Tags
+    /// can be given by name or alias. An empty `names` returns the untagged media, and an unknown
+    /// tag matches nothing.
+    pub fn media_with_exact_tags(&self, names: &[&str]) -> Result<Vec<Medium>> {
+        let mut tag_ids = Vec::new();
+        for name in names {
+            match self.get_tag_id(name) {
+                Ok(id) => tag_ids.push(id),
+                Err(Error::TagDoesNotExist(_)) => return Ok(Vec::new()),
+                Err(e) => return Err(e),
+            }
+        }
+        // The same tag may be given twice, e.g. by name and alias
+        tag_ids.sort_unstable();
+        tag_ids.dedup();
+
+        let filter = if tag_ids.is_empty() {
+            "WHERE NOT EXISTS(SELECT 1 FROM media_tags x WHERE x.media_id = m.id)".to_string()
+        } else {
+            let placeholders = vec!["?"; tag_ids.len()].join(",");
+            format!(
+                "WHERE m.id IN (SELECT media_id FROM media_tags GROUP BY media_id
+                                HAVING COUNT(DISTINCT tag_id) = {count}
+                                   AND COUNT(DISTINCT CASE WHEN tag_id IN ({placeholders})
+                                                      THEN tag_id END) = {count})",
+                count = tag_ids.len()
+            )
+        };
+        Ok(self
+            .load_media_tag_where(&filter, "", params_from_iter(&tag_ids))?
+            .media)
+    }
+
+    /// Loads media with their tags. `filter` is a `WHERE` clause on `media m` and `order` an
+    /// `ORDER BY`/`LIMIT` clause applied to the grouped rows.
+    fn load_media_tag_where<P: Params>(
+        &self,
+        filter: &str,
+        order: &str,
+        params: P,
+    ) -> Result<MediaTags> {
+        let tag_id_map = self.get_tag_id_map()?;
+        let root = self.output_root();
+
+        let mut stmt = self.connection.prepare(&format!(
+            "SELECT m.id, m.path, GROUP_CONCAT(t.id, ','), m.note, m.rating, m.mtime
+             FROM media m
+             LEFT JOIN media_tags mt ON m.id = mt.media_id
+             LEFT JOIN tags t ON mt.tag_id = t.id
+             {filter}
+             GROUP BY m.id
+             {order}"
+        ))?;
+
+        let media = stmt
+            .query_map(params, |row| {
+                let path_string: String = row.get(1)?;
+                let relative = from_db_string(Path::new(""), &path_string);
+                let path = root.join(&relative);
+
+                let tag_id_string: Option<String> = row.get(2)?;
+                let mut tags: Vec<i64> = match tag_id_string {
+                    Some(s) => s.split(',').filter_map(|x| x.parse::<i64>().ok()).collect(),
+                    None => Vec::new(),
+                };
+                tags.sort_by(|a, b| tag_id_map.get(a).cmp(&tag_id_map.get(b)));
+
+                Ok(Medium {
+                    id: row.get(0)?,
+                    path,
+                    relative,
+                    tags,
+                    note: row.get(3)?,
+                    rating: row.get(4)?,
+                    mtime: row.get(5)?,
+                })
+            })?
+            .collect::<std::result::Result<Vec<Medium>, _>>()?;
+
+        Ok(MediaTags {
+            media,
             tags: tag_id_map,
         })
     }
+
+    /// Returns up to `limit` media ordered by how many tags they have, most tagged first unless
+    /// `ascending` is set. Untagged media are included with a count of 0.
+    pub fn media_by_tag_count(&self, ascending: bool, limit: usize) -> Result<Vec<(Medium, i64)>> {
+        let direction = if ascending { "ASC" } else { "DESC" };
+        let media_tag_data = self.load_media_tag_where(
+            "",
+            &format!("ORDER BY COUNT(t.id) {direction}, m.path LIMIT ?1"),
+            (limit as i64,),
+        )?;
+
+        Ok(media_tag_data
+            .media
+            .into_iter()
+            .map(|medium| {
+                let count = medium.tags.len() as i64;
+                (medium, count)
+            })
+            .collect())
+    }
+
+    /// Returns the other media sharing at least `min_shared` (and at least one) tags with the file
+    /// at `path`, along with the number of shared tags, most shared first and then by path.
+    pub fn similar_media<P: AsRef<Path>>(
+        &self,
+        path: P,
+        min_shared: usize,
+    ) -> Result<Vec<(Medium, usize)>> {
+        let medium = self.get_medium_by_path(path)?;
+        let tags: HashSet<i64> = medium.tags.iter().copied().collect();
+
+        let media_tag_data = self.load_media_tag_where(
+            "WHERE m.id IN (
+                 SELECT other.media_id FROM media_tags this
+                 JOIN media_tags other ON this.tag_id = other.tag_id
+                 WHERE this.media_id = ?1 AND other.media_id <> ?1
+             )",
+            "",
+            (medium.id,),
+        )?;
+        let mut similar: Vec<(Medium, usize)> = media_tag_data
+            .media
+            .into_iter()
+            .map(|other| {
+                let shared = other.tags.iter().filter(|id| tags.contains(id)).count();
+                (other, shared)
+            })
+            .filter(|(_, shared)| *shared >= min_shared)
+            .collect();
+        similar.sort_by(|(a, a_shared), (b, b_shared)| {
+            b_shared.cmp(a_shared).then_with(|| a.path.cmp(&b.path))
+        });
+
+        Ok(similar)
+    }
+
+    /// Writes a sidecar file containing the tag names (one per line) next to every tagged medium.
+    /// A file that cannot be written is reported through `on_error` and does not abort the run.
+    /// Returns the number of sidecar files written.
+    pub fn export_sidecars<F: FnMut(&Path, Error)>(&self, mut on_error: F) -> Result<usize> {
+        let media_tag_data = self.load_media_tag()?;
+
+        let mut written = 0;
+        for medium in media_tag_data.media.iter().filter(|m| !m.tags.is_empty()) {
+            let tag_names = media_tag_data.names(medium);
+
+            let mut contents = tag_names.join("\n");
+            contents.push('\n');
+
+            let sidecar = sidecar_path(self.media_root().join(&medium.relative));
+            match fs::write(&sidecar, contents) {
+                Ok(()) => written += 1,
+                Err(e) => on_error(&sidecar, e.into()),
+            }
+        }
+
+        Ok(written)
+    }
+
+    /// Exports all media as CSV with the columns `path,tags`. Paths are relative to the library
+    /// root and the tag names of a medium are joined with `;`. The CSV is preceded by a line
+    /// containing the [`EXPORT_FORMAT_VERSION`].
+    pub fn export_csv(&self) -> Result<String> {
+        let mut bytes = Vec::new();
+        self.write_csv(&mut bytes, DEFAULT_BATCH_SIZE)?;
+        Ok(String::from_utf8(bytes).expect("CSV is written from UTF-8 strings only"))
+    }
+
+    /// Writes the export of [`MediaTag::export_csv`] to `writer`, loading `batch_size` media at a
+    /// time instead of the whole library.
+    pub fn write_csv<W: Write>(&self, mut writer: W, batch_size: usize) -> Result<()> {
+        writeln!(writer, "{EXPORT_HEADER}{EXPORT_FORMAT_VERSION}")?;
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record(["path", "tags"])?;
+
+        self.for_each_media_batch(batch_size, |batch| {
+            for medium in &batch.media {
+                let tag_names = batch.names(medium);
+                writer.write_record([to_db_string(&medium.relative)?, tag_names.join(";")])?;
+            }
+            Ok(())
+        })?;
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Calls `f` with all media in batches of `batch_size`, ordered by id.
+    fn for_each_media_batch(
+        &self,
+        batch_size: usize,
+        mut f: impl FnMut(&MediaTags) -> Result<()>,
+    ) -> Result<()> {
+        let mut last_id = i64::MIN;
+        loop {
+            let batch = self.load_media_tag_where(
+                "WHERE m.id > ?1",
+                "ORDER BY m.id LIMIT ?2",
+                (last_id, batch_size.max(1)),
+            )?;
+            let Some(last) = batch.media.last() else {
+                return Ok(());
+            };
+            last_id = last.id;
+
+            f(&batch)?;
+        }
+    }
+
+    /// Imports files and their tags in the format written by [`MediaTag::export_csv`]. Missing
+    /// tags are created. Everything is imported in one transaction, so nothing is changed if the
+    /// import fails. Returns the number of tags that were added to files.
+    ///
+    /// Exports of a newer [`EXPORT_FORMAT_VERSION`] are refused with
+    /// [`Error::UnsupportedExportVersion`]; exports without a version line are read as version 1.
+    pub fn import_csv<R: Read>(&self, reader: R, policy: ConflictPolicy) -> Result<usize> {
+        self.import_csv_batched(reader, policy, DEFAULT_BATCH_SIZE)
+    }
+
+    /// Like [`MediaTag::import_csv`], but reads and inserts `batch_size` files at a time. Larger
+    /// batches need fewer statements, smaller ones less memory. The import still happens in one
+    /// transaction.
+    pub fn import_csv_batched<R: Read>(
+        &self,
+        reader: R,
+        policy: ConflictPolicy,
+        batch_size: usize,
+    ) -> Result<usize> {
+        let mut reader = BufReader::new(reader);
+        let mut first_line = String::new();
+        reader.read_line(&mut first_line)?;
+        if let Some(version) = first_line.strip_prefix(EXPORT_HEADER) {
+            let version = version.trim();
+            if !version
+                .parse::<u32>()
+                .is_ok_and(|version| version <= EXPORT_FORMAT_VERSION)
+            {
+                return Err(Error::UnsupportedExportVersion(version.to_string()));
+            }
+            first_line.clear();
+        }
+        // Without a version line, the first line is already the CSV header
+        let reader = Cursor::new(first_line).chain(reader);
+
+        let media = csv::Reader::from_reader(reader)
+            .into_records()
+            .filter_map(|record| {
+                let record = match record {
+                    Ok(record) => record,
+                    Err(e) => return Some(Err(e.into())),
+                };
+                let path_str = record.get(0).unwrap_or_default();
+                if path_str.is_empty() {
+                    return None;
+                }
+                let tags = record.get(1).unwrap_or_default();
+                let tags = tags
+                    .split(';')
+                    .filter(|tag| !tag.is_empty())
+                    .map(str::to_string)
+                    .collect();
+                Some(Ok((path_str.to_string(), tags)))
+            });
+
+        self.import_media(&[], media, policy, batch_size)
+    }
+
+    /// Exports all tags and media as TOML: the tag names in `tags` and every file (relative to
+    /// the library root) with the names of its tags in the `media` table, after the
+    /// [`EXPORT_FORMAT_VERSION`] in `format_version`.
+    ///
+    /// ```toml
+    /// format_version = 1
+    /// tags = ["music", "vacation"]
+    ///
+    /// [media]
+    /// "photos/beach.jpg" = ["vacation"]
+    /// ```
+    pub fn export_toml(&self) -> Result<String> {
+        let media_tag_data = self.load_media_tag()?;
+
+        let mut media = toml::Table::new();
+        for medium in &media_tag_data.media {
+            let tag_names = media_tag_data.names(medium);
+            media.insert(to_db_string(&medium.relative)?, tag_names.into());
+        }
+        let tag_names: Vec<String> = self.get_tags()?.into_iter().map(|tag| tag.name).collect();
+
+        let mut export = toml::Table::new();
+        export.insert(
+            "format_version".to_string(),
+            i64::from(EXPORT_FORMAT_VERSION).into(),
+        );
+        export.insert("tags".to_string(), tag_names.into());
+        export.insert("media".to_string(), media.into());
+        Ok(export.to_string())
+    }
+
+    /// Imports tags and media in the format written by [`MediaTag::export_toml`], like
+    /// [`MediaTag::import_csv`]. Tags listed in `tags` are created even if no file has them.
+    pub fn import_toml(&self, contents: &str, policy: ConflictPolicy) -> Result<usize> {
+        let export: toml::Table = contents.parse()?;
+
+        let version = export
+            .get("format_version")
+            .and_then(toml::Value::as_integer)
+            .unwrap_or(1);
+        if !(1..=i64::from(EXPORT_FORMAT_VERSION)).contains(&version) {
+            return Err(Error::UnsupportedExportVersion(version.to_string()));
+        }
+
+        let tags = match export.get("tags") {
+            Some(tags) => string_array(tags, "tags")?,
+            None => Vec::new(),
+        };
+        let mut media = Vec::new();
+        if let Some(table) = export.get("media") {
+            let table = table
+                .as_table()
+                .ok_or_else(|| Error::InvalidExport("`media` must be a table".to_string()))?;
+            for (path_str, tags) in table {
+                media.push((path_str.clone(), string_array(tags, path_str)?));
+            }
+        }
+
+        self.import_media(&tags, media.into_iter().map(Ok), policy, DEFAULT_BATCH_SIZE)
+    }
+
+    /// Creates `tags` and adds every `(path, tags)` pair of `media` in one transaction, creating
+    /// missing tags. Tags are created like [`MediaTag::create_tag`] and paths are checked with
+    /// [`parse_stored_path`]. The files are inserted `batch_size` at a time. Returns the number of
+    /// tags that were added to files.
+    fn import_media(
+        &self,
+        tags: &[String],
+        mut media: impl Iterator<Item = Result<(String, Vec<String>)>>,
+        policy: ConflictPolicy,
+        batch_size: usize,
+    ) -> Result<usize> {
+        let tx = self.connection.unchecked_transaction()?;
+        let result = (|| {
+            let mut tag_ids = HashMap::new();
+            let mut get_tag_id = |tag: &str| -> Result<i64> {
+                if let Some(&id) = tag_ids.get(tag) {
+                    return Ok(id);
+                }
+                validate_tag_name(tag)?;
+                if self.get_tag_id(tag).is_err() {
+                    self.create_tag(tag)?;
+                }
+                let id = self.get_tag_id(tag)?;
+                tag_ids.insert(tag.to_string(), id);
+                Ok(id)
+            };
+            for tag in tags {
+                get_tag_id(tag)?;
+            }
+
+            let mut added = 0;
+            // Media listed more than once keep the tags of all their rows
+            let mut cleared = HashSet::new();
+            let mut batch = Vec::with_capacity(batch_size.max(1));
+            loop {
+                batch.clear();
+                for entry in media.by_ref().take(batch_size.max(1)) {
+                    let (path_str, tags) = entry?;
+                    batch.push((parse_stored_path(&path_str)?, tags));
+                }
+                if batch.is_empty() {
+                    break;
+                }
+                let medium_ids = insert_media_paths(
+                    &tx,
+                    self.media_root(),
+                    batch.iter().map(|(path, _)| path.as_str()),
+                )?;
+
+                for (path_str, tags) in &batch {
+                    let medium_id = medium_ids[path_str];
+
+                    if policy == ConflictPolicy::Replace && cleared.insert(medium_id) {
+                        tx.execute("DELETE FROM media_tags WHERE media_id = ?1", (medium_id,))?;
+                    }
+
+                    for tag in tags {
+                        let tag_id = get_tag_id(tag)?;
+
+                        let inserted = tx.execute(
+                            "INSERT OR IGNORE INTO media_tags(media_id, tag_id, created_at)
+                             VALUES (?1, ?2, unixepoch())",
+                            (medium_id, tag_id),
+                        )?;
+                        if inserted == 0 && policy == ConflictPolicy::Error {
+                            return Err(Error::AssociationAlreadyExists(
+                                path_str.clone(),
+                                tag.clone(),
+                            ));
+                        }
+                        added += inserted;
+                    }
+                }
+            }
+            Ok(added)
+        })();
+        match result {
+            Ok(added) => {
+                tx.commit()?;
+                Ok(added)
+            }
+            Err(e) => {
+                // Tags created before the failure are rolled back
+                self.refresh_tag_cache();
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns every pair of tags that occur together on at least `min` media, along with the
+    /// number of media they share. Each pair is reported once, ordered by tag name.
+    pub fn tag_cooccurrence(&self, min: i64) -> Result<Vec<(String, String, i64)>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT a.name, b.name, COUNT(*) AS shared
+             FROM media_tags x
+             JOIN media_tags y ON x.media_id = y.media_id AND x.tag_id <> y.tag_id
+             JOIN tags a ON a.id = x.tag_id
+             JOIN tags b ON b.id = y.tag_id
+             WHERE a.name < b.name
+             GROUP BY x.tag_id, y.tag_id
+             HAVING shared >= ?1
+             ORDER BY a.name, b.name",
+        )?;
+        let pairs = stmt
+            .query_map((min,), |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect::<std::result::Result<Vec<(String, String, i64)>, _>>()?;
+
+        Ok(pairs)
+    }
+
+    /// Returns every distinct set of tags that occurs on a medium, with the number of media that
+    /// have exactly that set. The names in a set are sorted; the empty set counts the untagged
+    /// media. Sets are ordered by count, most frequent first, then by their names.
+    pub fn tag_set_distribution(&self) -> Result<Vec<(Vec<String>, i64)>> {
+        let tag_id_map = self.get_tag_id_map()?;
+
+        let mut stmt = self.connection.prepare(
+            "SELECT m.id, mt.tag_id
+             FROM media m
+             LEFT JOIN media_tags mt ON m.id = mt.media_id",
+        )?;
+        let mut media: HashMap<i64, Vec<i64>> = HashMap::new();
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let tag_ids = media.entry(row.get(0)?).or_default();
+            if let Some(tag_id) = row.get::<_, Option<i64>>(1)? {
+                tag_ids.push(tag_id);
+            }
+        }
+
+        let mut counts: HashMap<Vec<&str>, i64> = HashMap::new();
+        for tag_ids in media.values() {
+            let mut names: Vec<&str> = tag_ids
+                .iter()
+                .filter_map(|id| tag_id_map.get(id).map(String::as_str))
+                .collect();
+            names.sort_unstable();
+            *counts.entry(names).or_default() += 1;
+        }
+
+        let mut distribution: Vec<(Vec<String>, i64)> = counts
+            .into_iter()
+            .map(|(names, count)| (names.into_iter().map(str::to_string).collect(), count))
+            .collect();
+        distribution.sort_unstable_by(|(a, a_count), (b, b_count)| {
+            b_count.cmp(a_count).then_with(|| a.cmp(b))
+        });
+        Ok(distribution)
+    }
+
+    /// Renders the tag co-occurrence as a Graphviz graph: every tag is a node and tags sharing at
+    /// least `min` media are connected by an edge weighted with the number of shared media.
+    pub fn export_cooccurrence_dot(&self, min: i64) -> Result<String> {
+        let tags = self.get_tags()?;
+
+        let mut dot = String::from("graph tags {\n");
+        for tag in &tags {
+            dot.push_str(&format!("  \"{}\";\n", escape_dot(&tag.name)));
+        }
+        for (a, b, shared) in self.tag_cooccurrence(min)? {
+            dot.push_str(&format!(
+                "  \"{}\" -- \"{}\" [weight={shared}, label=\"{shared}\"];\n",
+                escape_dot(&a),
+                escape_dot(&b)
+            ));
+        }
+        dot.push_str("}\n");
+
+        Ok(dot)
+    }
+}
+
+/// Matches `name` against a pattern split at its `*` wildcards and returns the parts of `name`
+/// matched by each wildcard. Wildcards match as little as possible.
+fn match_wildcards<'a>(parts: &[&str], name: &'a str) -> Option<Vec<&'a str>> {
+    let (first, rest) = parts.split_first()?;
+    let mut remaining = name.strip_prefix(first)?;
+    let Some((last, middle)) = rest.split_last() else {
+        return remaining.is_empty().then(Vec::new);
+    };
+
+    let mut captures = Vec::with_capacity(rest.len());
+    for part in middle {
+        let start = remaining.find(part)?;
+        captures.push(&remaining[..start]);
+        remaining = &remaining[start + part.len()..];
+    }
+    captures.push(remaining.strip_suffix(last)?);
+    Some(captures)
+}
+
+/// Reads a TOML array of strings, `key` names the value in errors.
+fn string_array(value: &toml::Value, key: &str) -> Result<Vec<String>> {
+    let invalid = || Error::InvalidExport(format!("`{key}` must be a list of tag names"));
+
+    value
+        .as_array()
+        .ok_or_else(invalid)?
+        .iter()
+        .map(|tag| tag.as_str().map(str::to_string).ok_or_else(invalid))
+        .collect()
+}
+
+fn find_root(parent: &mut [usize], i: usize) -> usize {
+    let mut root = i;
+    while parent[root] != root {
+        root = parent[root];
+    }
+    parent[i] = root;
+    root
+}
+
+/// Normalized edit distance similarity: 1.0 for equal strings, 0.0 for entirely different ones.
+fn similarity(a: &[char], b: &[char]) -> f64 {
+    let max_len = a.len().max(b.len());
+    if max_len == 0 {
+        return 1.0;
+    }
+    1.0 - levenshtein(a, b) as f64 / max_len as f64
+}
+
+fn levenshtein(a: &[char], b: &[char]) -> usize {
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    let mut current = vec![0; b.len() + 1];
+    for (i, ca) in a.iter().enumerate() {
+        current[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        std::mem::swap(&mut previous, &mut current);
+    }
+    previous[b.len()]
+}
+
+fn escape_dot(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Makes `path` absolute and removes `.` and `..` components without accessing the file system.
+/// Unlike [`Path::canonicalize`], the file does not have to exist and symbolic links are kept.
+fn normalize_path(path: &Path) -> Result<PathBuf> {
+    let mut normalized = std::env::current_dir()?;
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    Ok(normalized)
+}
+
+/// Checks a path read from an import or a tag spec, which must be relative to the library root,
+/// and converts it into the stored form: `\` separates components like `/`, and empty and `.`
+/// components are dropped. Absolute paths and `..` components are rejected with
+/// [`Error::InvalidExport`], so that nothing outside the library can be tracked.
+fn parse_stored_path(path_str: &str) -> Result<String> {
+    let invalid = |reason: &str| Error::InvalidExport(format!("path \"{path_str}\" {reason}"));
+
+    let has_root = Path::new(path_str)
+        .components()
+        .any(|c| matches!(c, Component::Prefix(_) | Component::RootDir));
+    if has_root || path_str.starts_with(['/', '\\']) {
+        return Err(invalid("must be relative to the library root"));
+    }
+    let mut components = Vec::new();
+    for component in path_str.split(['/', '\\']) {
+        match component {
+            "" | "." => {}
+            ".." => return Err(invalid("must not contain `..`")),
+            component => components.push(component),
+        }
+    }
+    if components.is_empty() {
+        return Err(invalid("does not name a file"));
+    }
+
+    Ok(components.join("/"))
+}
+
+/// Converts a path relative to the library root into the form stored in the database, which
+/// always uses `/` as separator so that a library can be shared between operating systems.
+fn to_db_string(rel_path: &Path) -> Result<String> {
+    let components = rel_path
+        .components()
+        .map(|c| {
+            c.as_os_str()
+                .to_str()
+                .ok_or_else(|| Error::InvalidPathEncoding(rel_path.to_path_buf()))
+        })
+        .collect::<Result<Vec<&str>>>()?;
+
+    Ok(components.join("/"))
+}
+
+/// Inserts the media stored at `path_strs` that are not tracked yet with as few statements as
+/// possible, records the modification times of the files below `root` and returns the ids of all
+/// of them by path.
+fn insert_media_paths<'a>(
+    connection: &Connection,
+    root: &Path,
+    path_strs: impl IntoIterator<Item = &'a str>,
+) -> Result<HashMap<String, i64>> {
+    // A single upsert must not touch the same row twice
+    let mut seen = HashSet::new();
+    let path_strs: Vec<&str> = path_strs
+        .into_iter()
+        .filter(|path_str| seen.insert(*path_str))
+        .collect();
+
+    let mut ids = HashMap::with_capacity(path_strs.len());
+    for chunk in path_strs.chunks(MAX_SQL_VARIABLES / 2) {
+        let placeholders = vec!["(?, ?)"; chunk.len()].join(",");
+        let mut stmt = connection.prepare(&format!(
+            "INSERT INTO media (path, mtime) VALUES {placeholders}
+             ON CONFLICT(path) DO UPDATE SET mtime=COALESCE(excluded.mtime, mtime)
+             RETURNING id, path"
+        ))?;
+        let params = chunk.iter().flat_map(|path_str| {
+            let mtime = file_mtime(&from_db_string(root, path_str));
+            [Value::from(path_str.to_string()), Value::from(mtime)]
+        });
+        let mut rows = stmt.query(params_from_iter(params))?;
+        while let Some(row) = rows.next()? {
+            ids.insert(row.get::<_, String>(1)?, row.get::<_, i64>(0)?);
+        }
+    }
+    Ok(ids)
+}
+
+/// Returns the modification time of the file at `path` in seconds since the Unix epoch, or `None`
+/// if it cannot be determined.
+fn file_mtime(path: &Path) -> Option<i64> {
+    unix_seconds(fs::metadata(path).ok()?.modified().ok()?)
+}
+
+/// Converts `time` to seconds since the Unix epoch, `None` if it lies before it.
+fn unix_seconds(time: SystemTime) -> Option<i64> {
+    i64::try_from(time.duration_since(UNIX_EPOCH).ok()?.as_secs()).ok()
+}
+
+/// The inverse of [`to_db_string`]: joins a stored path onto `root` component by component.
+/// Both `/` and `\` separate components, so paths written by older Windows builds read back too.
+fn from_db_string(root: &Path, path_str: &str) -> PathBuf {
+    let mut path = root.to_path_buf();
+    path.extend(path_str.split(['/', '\\']).filter(|c| !c.is_empty()));
+    path
+}
+
+fn log_statement(event: TraceEvent<'_>) {
+    if let TraceEvent::Profile(stmt, duration) = event {
+        log::debug!(
+            "{:.3}ms {}",
+            duration.as_secs_f64() * 1000.0,
+            stmt.sql().trim()
+        );
+    }
+}
+
+/// FNV-1a hash of `SQL_SCRIPT` and `MIGRATIONS`, identifying the schema this version expects.
+fn schema_checksum() -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for part in std::iter::once(SQL_SCRIPT).chain(MIGRATIONS.iter().copied()) {
+        for byte in part.bytes().chain([0]) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{hash:016x}")
+}
+
+fn apply_migrations(connection: &Connection) -> Result<()> {
+    let version: usize = connection.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(Error::UnknownSchemaVersion(version));
+    }
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        connection.execute_batch(migration)?;
+        connection.pragma_update(None, "user_version", i + 1)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::Deref;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A library in a fresh directory below the temporary directory, deleted when dropped.
+    struct TempLibrary {
+        dir: PathBuf,
+        media_tag: MediaTag,
+    }
+
+    impl TempLibrary {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let dir = std::env::temp_dir().join(format!(
+                "media_tag_test_{}_{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            let media_tag = MediaTag::create(dir.join(".media_tag.db")).unwrap();
+            Self { dir, media_tag }
+        }
+
+        /// Creates the file `rel_path` and adds `tags` to it, creating missing tags.
+        fn tag(&self, rel_path: &str, tags: &[&str]) -> PathBuf {
+            let path = self.dir.join(rel_path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "").unwrap();
+            self.track(&path).unwrap();
+            for tag in tags {
+                self.add_tag_creating(&path, tag).unwrap();
+            }
+            path
+        }
+
+        /// Every stored path with the names of its tags, sorted by path.
+        fn tags_by_path(&self) -> Vec<(String, Vec<String>)> {
+            let data = self.load_media_tag().unwrap();
+            let mut media: Vec<(String, Vec<String>)> = data
+                .media
+                .iter()
+                .map(|medium| {
+                    let names = data.names(medium).into_iter().map(str::to_string);
+                    (to_db_string(&medium.relative).unwrap(), names.collect())
+                })
+                .collect();
+            media.sort();
+            media
+        }
+    }
+
+    impl Deref for TempLibrary {
+        type Target = MediaTag;
+
+        fn deref(&self) -> &MediaTag {
+            &self.media_tag
+        }
+    }
+
+    impl Drop for TempLibrary {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    /// The stored paths of `media`, sorted.
+    fn stored_paths(media: &[Medium]) -> Vec<String> {
+        let mut paths: Vec<String> = media
+            .iter()
+            .map(|medium| to_db_string(&medium.relative).unwrap())
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn db_strings_round_trip_with_either_separator() {
+        let root = Path::new("library");
+        let native: PathBuf = ["photos", "2023", "beach.jpg"].iter().collect();
+        assert_eq!(to_db_string(&native).unwrap(), "photos/2023/beach.jpg");
+        assert_eq!(
+            from_db_string(root, "photos/2023/beach.jpg"),
+            root.join(&native)
+        );
+
+        // A path stored with Windows separators reads back the same on every platform
+        let decoded = from_db_string(root, r"photos\2023\beach.jpg");
+        assert_eq!(decoded, root.join(&native));
+        assert_eq!(decoded.strip_prefix(root).unwrap().components().count(), 3);
+        assert_eq!(
+            from_db_string(Path::new(""), r"dir\file"),
+            ["dir", "file"].iter().collect::<PathBuf>()
+        );
+    }
+
+    #[test]
+    fn csv_export_with_version_header_round_trips() {
+        let source = TempLibrary::new();
+        source.tag("photos/beach.jpg", &["vacation", "2023"]);
+        source.tag("notes.txt", &["work"]);
+        source.tag("untagged.jpg", &[]);
+        let export = source.export_csv().unwrap();
+        assert!(export.starts_with(&format!("{EXPORT_HEADER}{EXPORT_FORMAT_VERSION}\n")));
+
+        let target = TempLibrary::new();
+        assert_eq!(
+            target
+                .import_csv(export.as_bytes(), ConflictPolicy::Skip)
+                .unwrap(),
+            3
+        );
+        assert_eq!(target.tags_by_path(), source.tags_by_path());
+    }
+
+    #[test]
+    fn csv_import_refuses_future_export_version() {
+        let source = TempLibrary::new();
+        source.tag("beach.jpg", &["vacation"]);
+        let export = source.export_csv().unwrap().replacen(
+            &format!("{EXPORT_HEADER}{EXPORT_FORMAT_VERSION}"),
+            &format!("{EXPORT_HEADER}{}", EXPORT_FORMAT_VERSION + 1),
+            1,
+        );
+
+        let target = TempLibrary::new();
+        let result = target.import_csv(export.as_bytes(), ConflictPolicy::Skip);
+        assert!(matches!(result, Err(Error::UnsupportedExportVersion(_))));
+        assert_eq!(target.media_count().unwrap(), 0);
+    }
+
+    /// A library with `a: x`, `b: x, y`, `c: y` and the untagged `d`.
+    fn query_library() -> TempLibrary {
+        let library = TempLibrary::new();
+        library.tag("a", &["x"]);
+        library.tag("b", &["x", "y"]);
+        library.tag("c", &["y"]);
+        library.tag("d", &[]);
+        library
+    }
+
+    #[test]
+    fn query_include_only() {
+        let library = query_library();
+        let query =
+            |include: &[&str], any| stored_paths(&library.query(include, &[], any).unwrap());
+
+        assert_eq!(query(&["x"], false), ["a", "b"]);
+        assert_eq!(query(&["x", "y"], false), ["b"]);
+        assert_eq!(query(&["x", "y"], true), ["a", "b", "c"]);
+        assert!(query(&["x", "unknown"], false).is_empty());
+        assert_eq!(query(&["x", "unknown"], true), ["a", "b"]);
+    }
+
+    #[test]
+    fn query_exclude_only() {
+        let library = query_library();
+        let query = |exclude: &[&str]| stored_paths(&library.query(&[], exclude, false).unwrap());
+
+        assert_eq!(query(&["x"]), ["c", "d"]);
+        assert_eq!(query(&["x", "y"]), ["d"]);
+        assert_eq!(query(&["unknown"]), ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn query_include_and_exclude() {
+        let library = query_library();
+        let query = |include: &[&str], exclude: &[&str], any| {
+            stored_paths(&library.query(include, exclude, any).unwrap())
+        };
+
+        assert_eq!(query(&["x"], &["y"], false), ["a"]);
+        assert_eq!(query(&["y"], &["x"], false), ["c"]);
+        assert_eq!(query(&["x", "y"], &["x"], true), ["c"]);
+        assert!(query(&["x"], &["x"], false).is_empty());
+    }
+
+    #[test]
+    fn normalize_path_removes_dot_components() {
+        let base = std::env::temp_dir();
+        assert_eq!(
+            normalize_path(&base.join("a/./b/../c.jpg")).unwrap(),
+            base.join("a").join("c.jpg")
+        );
+        assert_eq!(normalize_path(&base.join("a/..")).unwrap(), base);
+    }
+
+    #[test]
+    fn non_canonical_paths_are_stored_lexically() {
+        let mut library = TempLibrary::new();
+        library.media_tag.set_canonicalize_paths(false);
+        library.create_tag("offline").unwrap();
+
+        // Neither the file nor its directory exist
+        let missing = library.dir.join("volume/photo.jpg");
+        assert!(library.add_tag(&missing, "offline").unwrap());
+        assert_eq!(library.stored_path(&missing).unwrap(), "volume/photo.jpg");
+
+        let dotted = library.dir.join("./volume/other/../photo.jpg");
+        assert!(!library.add_tag(&dotted, "offline").unwrap());
+        assert_eq!(library.media_count().unwrap(), 1);
+
+        let outside = library.dir.join("../photo.jpg");
+        assert!(matches!(
+            library.add_tag(&outside, "offline"),
+            Err(Error::OutsideLibraryRoot { .. })
+        ));
+    }
+
+    #[test]
+    fn metadata_is_available_without_root() {
+        let library = TempLibrary::new();
+        // Stands in for a volume that is not mounted
+        let root = library.dir.join("unmounted");
+        let media_tag =
+            MediaTag::from_connection(Connection::open_in_memory().unwrap(), root.clone()).unwrap();
+
+        media_tag.create_tag("offline").unwrap();
+        let tags: Vec<String> = media_tag
+            .get_tags()
+            .unwrap()
+            .into_iter()
+            .map(|tag| tag.name)
+            .collect();
+        assert_eq!(tags, ["offline"]);
+        assert!(media_tag.load_media_tag().unwrap().media.is_empty());
+
+        assert!(
+            media_tag
+                .add_tag(root.join("photo.jpg"), "offline")
+                .is_err()
+        );
+        assert!(media_tag.stored_path(root.join("photo.jpg")).is_err());
+        assert!(
+            media_tag
+                .root()
+                .is_err_and(|e| matches!(e, Error::RootUnavailable(..)))
+        );
+    }
+
+    #[test]
+    fn imports_reject_invalid_tag_names() {
+        let library = TempLibrary::new();
+        let photo = library.tag("photo.jpg", &[]);
+
+        let media = vec![Ok(("photo.jpg".to_string(), vec!["a,b".to_string()]))];
+        assert!(matches!(
+            library.import_media(&[], media.into_iter(), ConflictPolicy::Skip, 100),
+            Err(Error::InvalidTagName(..))
+        ));
+        assert!(matches!(
+            library.set_tags(&photo, &["ok", " "]),
+            Err(Error::InvalidTagName(..))
+        ));
+        assert!(library.get_tags().unwrap().is_empty());
+    }
+
+    #[test]
+    fn tag_case_as_is_keeps_names() {
+        let library = TempLibrary::new();
+        library.tag("a.jpg", &["Summer Vacation"]);
+
+        assert!(library.delete_tag("summer vacation").is_err());
+        assert_eq!(
+            library
+                .rename_tags_by_pattern("Summer *", "Winter *")
+                .unwrap(),
+            [("Summer Vacation".to_string(), "Winter Vacation".to_string())]
+        );
+        let plan = library.delete_tag_plan("Winter Vacation").unwrap();
+        assert_eq!(plan.deleted_tags, ["Winter Vacation"]);
+        assert_eq!(plan.removed_associations, 1);
+    }
+
+    #[test]
+    fn tag_case_lower_applies_to_lookups_and_renames() {
+        let library = TempLibrary::new();
+        library.set_tag_case(TagCase::Lower).unwrap();
+        library.tag("a.jpg", &["Summer Vacation"]);
+        assert_eq!(library.tags_by_path()[0].1, ["summer vacation"]);
+
+        assert_eq!(
+            library
+                .rename_tags_by_pattern("summer *", "Winter *")
+                .unwrap(),
+            [("summer vacation".to_string(), "winter vacation".to_string())]
+        );
+        assert_eq!(
+            library
+                .delete_tag_plan("Winter Vacation")
+                .unwrap()
+                .deleted_tags,
+            ["winter vacation"]
+        );
+        library.delete_tag("WINTER VACATION").unwrap();
+        assert!(library.get_tags().unwrap().is_empty());
+    }
+
+    #[test]
+    fn tag_case_kebab_applies_to_lookups_and_renames() {
+        let library = TempLibrary::new();
+        library.set_tag_case(TagCase::Kebab).unwrap();
+        library.tag("a.jpg", &["Summer Vacation", "beach"]);
+        assert_eq!(library.tags_by_path()[0].1, ["beach", "summer-vacation"]);
+
+        assert_eq!(
+            library
+                .rename_tags_by_pattern("summer-*", "Winter *")
+                .unwrap(),
+            [("summer-vacation".to_string(), "winter-vacation".to_string())]
+        );
+        library.add_tag_alias("sand", "beach").unwrap();
+        assert_eq!(
+            library.delete_tag_plan("Sand").unwrap().deleted_tags,
+            ["beach"]
+        );
+        library.delete_tag("winter_vacation").unwrap();
+        library.delete_tag("sand").unwrap();
+        assert!(library.get_tags().unwrap().is_empty());
+    }
+
+    #[test]
+    fn exact_tags_exclude_near_misses() {
+        let library = TempLibrary::new();
+        library.tag("exact.jpg", &["trip", "beach"]);
+        library.tag("extra.jpg", &["trip", "beach", "night"]);
+        library.tag("missing.jpg", &["trip"]);
+
+        let media = library.media_with_exact_tags(&["beach", "trip"]).unwrap();
+        assert_eq!(stored_paths(&media), ["exact.jpg"]);
+        let media = library.media_with_exact_tags(&["trip"]).unwrap();
+        assert_eq!(stored_paths(&media), ["missing.jpg"]);
+    }
+
+    #[test]
+    fn get_tags_keeps_creation_order() {
+        let library = TempLibrary::new();
+        for name in ["vacation", "work", "beach"] {
+            library.create_tag(name).unwrap();
+        }
+        let names = |tags: Vec<Tag>| tags.into_iter().map(|tag| tag.name).collect::<Vec<_>>();
+
+        assert_eq!(
+            names(library.get_tags().unwrap()),
+            ["vacation", "work", "beach"]
+        );
+        assert_eq!(
+            names(
+                library
+                    .get_tags_filtered(None, TagOrder::Name, Some(2), 0)
+                    .unwrap()
+            ),
+            ["beach", "vacation"]
+        );
+    }
+
+    #[test]
+    fn tag_order_defaults_to_creation_order() {
+        let library = TempLibrary::new();
+        library.tag("a.jpg", &["work", "beach"]);
+        library.tag("b.jpg", &["beach"]);
+        let names = |tags: Vec<Tag>| tags.into_iter().map(|tag| tag.name).collect::<Vec<_>>();
+
+        let tags = library.get_tags_filtered(None, TagOrder::default(), None, 0);
+        assert_eq!(names(tags.unwrap()), ["work", "beach"]);
+        let tags = library.get_tags_filtered(None, TagOrder::Count, None, 0);
+        assert_eq!(names(tags.unwrap()), ["beach", "work"]);
+    }
+
+    #[test]
+    fn replacing_import_keeps_tags_of_repeated_paths() {
+        let library = TempLibrary::new();
+        library.tag("photo.jpg", &["old"]);
+
+        let media = [
+            ("photo.jpg".to_string(), vec!["beach".to_string()]),
+            ("photo.jpg".to_string(), vec!["night".to_string()]),
+        ];
+        // A batch size of 1 puts the rows into separate batches
+        for batch_size in [1, 100] {
+            let rows = media.clone().into_iter().map(Ok);
+            library
+                .import_media(&[], rows, ConflictPolicy::Replace, batch_size)
+                .unwrap();
+            assert_eq!(
+                library.tags_by_path(),
+                [(
+                    "photo.jpg".to_string(),
+                    vec!["beach".to_string(), "night".to_string()]
+                )]
+            );
+        }
+    }
 }
@@ -0,0 +1,51 @@
+use crate::{Error, MediaTag, Result};
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+
+/// Name of the lock file created in the library root by [`MediaTag::try_lock`].
+pub const LOCK_FILENAME: &str = ".media_tag.lock";
+
+/// Advisory lock on a library, released when dropped. See [`MediaTag::try_lock`].
+#[derive(Debug)]
+pub struct LibraryLock {
+    path: PathBuf,
+}
+
+impl Drop for LibraryLock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::warn!("failed to remove lock file {}: {e}", self.path.display());
+        }
+    }
+}
+
+impl MediaTag {
+    /// Acquires the advisory lock of the library by creating a [`LOCK_FILENAME`] file in its
+    /// root, failing with [`Error::LibraryBusy`] if another process holds it. Maintenance
+    /// operations that rewrite many rows (deleting, pruning or renaming tags, imports) should
+    /// hold the lock so that two of them never run at the same time. The lock is not checked by
+    /// any other operation.
+    ///
+    /// The lock file contains the id of the process holding it. It is left behind if the process
+    /// is killed and has to be deleted manually.
+    pub fn try_lock(&self) -> Result<LibraryLock> {
+        let path = self.media_root().join(LOCK_FILENAME);
+
+        let mut file = match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                return Err(Error::LibraryBusy(path));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let lock = LibraryLock { path };
+        writeln!(file, "{}", std::process::id())?;
+
+        Ok(lock)
+    }
+}
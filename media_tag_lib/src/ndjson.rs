@@ -0,0 +1,123 @@
+use crate::{
+    ConflictPolicy, Error, MediaTag, Result, parse_stored_path, to_db_string, validate_tag_name,
+};
+use std::io::{BufRead, BufReader, Read, Write};
+
+/// Outcome of [`MediaTag::import_ndjson`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    /// Number of files read from valid lines
+    pub files: usize,
+    /// Number of tags that were added to files
+    pub added: usize,
+    /// Number of malformed lines that were skipped
+    pub skipped: usize,
+}
+
+impl MediaTag {
+    /// Writes every file as a JSON object `{"path": ..., "tags": [...]}` on its own line, with
+    /// paths relative to the library root. Media are loaded `batch_size` at a time.
+    pub fn write_ndjson<W: Write>(&self, mut writer: W, batch_size: usize) -> Result<()> {
+        self.for_each_media_batch(batch_size, |batch| {
+            for medium in &batch.media {
+                let record = serde_json::json!({
+                    "path": to_db_string(&medium.relative)?,
+                    "tags": batch.names(medium),
+                });
+                writeln!(writer, "{record}")?;
+            }
+            Ok(())
+        })?;
+
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Imports newline-delimited JSON objects of the form written by [`MediaTag::write_ndjson`],
+    /// reading and inserting `batch_size` files at a time in one transaction. Missing tags are
+    /// created, blank lines are ignored.
+    ///
+    /// Malformed lines (invalid JSON, a missing `path` or one outside the library, tags that are
+    /// not strings or not valid tag names) are reported to `on_skip` with their line number and
+    /// skipped, unless `strict` is set, in which case the first one fails the import with
+    /// [`Error::InvalidLine`] and nothing is changed.
+    pub fn import_ndjson<R: Read>(
+        &self,
+        reader: R,
+        policy: ConflictPolicy,
+        batch_size: usize,
+        strict: bool,
+        mut on_skip: impl FnMut(usize, Error),
+    ) -> Result<ImportSummary> {
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+        let (mut line_number, mut files, mut skipped) = (0, 0, 0);
+
+        let media = std::iter::from_fn(|| {
+            loop {
+                line.clear();
+                match reader.read_until(b'\n', &mut line) {
+                    Ok(0) => return None,
+                    Ok(_) => line_number += 1,
+                    Err(e) => return Some(Err(e.into())),
+                }
+
+                match parse_line(&line) {
+                    Ok(None) => {}
+                    Ok(Some(entry)) => {
+                        files += 1;
+                        return Some(Ok(entry));
+                    }
+                    Err(e) if strict => {
+                        return Some(Err(Error::InvalidLine(line_number, Box::new(e))));
+                    }
+                    Err(e) => {
+                        skipped += 1;
+                        on_skip(line_number, e);
+                    }
+                }
+            }
+        });
+        let added = self.import_media(&[], media, policy, batch_size)?;
+
+        Ok(ImportSummary {
+            files,
+            added,
+            skipped,
+        })
+    }
+}
+
+/// Parses one line of an NDJSON import into a path and its tags, `None` for blank lines.
+fn parse_line(line: &[u8]) -> Result<Option<(String, Vec<String>)>> {
+    let line = std::str::from_utf8(line)
+        .map_err(|_| Error::InvalidExport("line is not valid UTF-8".to_string()))?
+        .trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+
+    let record: serde_json::Value = serde_json::from_str(line)?;
+    let path = record
+        .get("path")
+        .and_then(serde_json::Value::as_str)
+        .filter(|path| !path.is_empty())
+        .ok_or_else(|| Error::InvalidExport("`path` must be a non-empty string".to_string()))?;
+    let tags = match record.get("tags") {
+        Some(tags) => tags
+            .as_array()
+            .ok_or_else(|| Error::InvalidExport("`tags` must be a list".to_string()))?
+            .iter()
+            .map(|tag| {
+                let tag = tag.as_str().ok_or_else(|| {
+                    Error::InvalidExport("`tags` must only contain strings".to_string())
+                })?;
+                validate_tag_name(tag)?;
+                Ok(tag.to_string())
+            })
+            .collect::<Result<Vec<_>>>()?,
+        None => Vec::new(),
+    };
+
+    Ok(Some((parse_stored_path(path)?, tags)))
+}
@@ -0,0 +1,99 @@
+use crate::{Error, MediaTag, Medium, Result};
+use rusqlite::OptionalExtension;
+
+/// A search that can be stored in the library under a name, see [`MediaTag::save_query`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SearchQuery {
+    /// Tags the media must have (all of them, or at least one with `any`)
+    pub include: Vec<String>,
+    /// Tags the media must not have
+    pub exclude: Vec<String>,
+    pub any: bool,
+}
+
+impl MediaTag {
+    /// Returns the media matching `query`, see [`MediaTag::query`].
+    pub fn search(&self, query: &SearchQuery) -> Result<Vec<Medium>> {
+        let include: Vec<&str> = query.include.iter().map(String::as_str).collect();
+        let exclude: Vec<&str> = query.exclude.iter().map(String::as_str).collect();
+
+        self.query(&include, &exclude, query.any)
+    }
+
+    /// Stores `query` under `name`, replacing a query saved under the same name before.
+    pub fn save_query(&self, name: &str, query: &SearchQuery) -> Result<()> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO saved_queries (name, include, exclude, any)
+             VALUES (?1, ?2, ?3, ?4)",
+            (
+                name,
+                join_tags(&query.include),
+                join_tags(&query.exclude),
+                query.any,
+            ),
+        )?;
+        Ok(())
+    }
+
+    /// Returns the query saved under `name`.
+    pub fn get_saved_query(&self, name: &str) -> Result<SearchQuery> {
+        self.connection
+            .query_row(
+                "SELECT include, exclude, any FROM saved_queries WHERE name = ?1",
+                (name,),
+                |row| {
+                    Ok(SearchQuery {
+                        include: split_tags(&row.get::<_, String>(0)?),
+                        exclude: split_tags(&row.get::<_, String>(1)?),
+                        any: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?
+            .ok_or_else(|| Error::QueryDoesNotExist(name.to_string()))
+    }
+
+    /// Runs the query saved under `name` with [`MediaTag::search`].
+    pub fn run_saved_query(&self, name: &str) -> Result<Vec<Medium>> {
+        self.search(&self.get_saved_query(name)?)
+    }
+
+    /// Returns all saved queries with their names, ordered by name.
+    pub fn saved_queries(&self) -> Result<Vec<(String, SearchQuery)>> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT name, include, exclude, any FROM saved_queries ORDER BY name")?;
+        let queries = stmt
+            .query_map([], |row| {
+                let query = SearchQuery {
+                    include: split_tags(&row.get::<_, String>(1)?),
+                    exclude: split_tags(&row.get::<_, String>(2)?),
+                    any: row.get(3)?,
+                };
+                Ok((row.get(0)?, query))
+            })?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        Ok(queries)
+    }
+
+    /// Deletes the query saved under `name`.
+    pub fn delete_saved_query(&self, name: &str) -> Result<()> {
+        let deleted = self
+            .connection
+            .execute("DELETE FROM saved_queries WHERE name = ?1", (name,))?;
+        if deleted == 0 {
+            return Err(Error::QueryDoesNotExist(name.to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Tags are stored one per line.
+fn join_tags(tags: &[String]) -> String {
+    tags.join("\n")
+}
+
+fn split_tags(tags: &str) -> Vec<String> {
+    tags.lines().map(str::to_string).collect()
+}
@@ -0,0 +1,91 @@
+use crate::{Error, MediaTag, Result};
+use rusqlite::OptionalExtension;
+use std::fmt;
+
+/// How the names of new tags are written. The casing is stored in the library, so it applies to
+/// every process opening it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TagCase {
+    /// Names are stored as given
+    #[default]
+    AsIs,
+    /// `Summer Vacation` becomes `summer vacation`
+    Lower,
+    /// `Summer Vacation` and `summer_vacation` become `summer-vacation`
+    Kebab,
+}
+
+impl TagCase {
+    pub const ALL: &[TagCase] = &[TagCase::AsIs, TagCase::Lower, TagCase::Kebab];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::AsIs => "as-is",
+            Self::Lower => "lower",
+            Self::Kebab => "kebab",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|case| case.name() == name)
+    }
+
+    /// Writes `name` in this casing.
+    pub fn apply(self, name: &str) -> String {
+        match self {
+            Self::AsIs => name.to_string(),
+            Self::Lower => name.to_lowercase(),
+            Self::Kebab => name
+                .split(|c: char| c.is_whitespace() || c == '_')
+                .filter(|part| !part.is_empty())
+                .map(str::to_lowercase)
+                .collect::<Vec<_>>()
+                .join("-"),
+        }
+    }
+}
+
+impl fmt::Display for TagCase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl MediaTag {
+    /// Returns the casing applied to tag names, see [`MediaTag::set_tag_case`].
+    pub fn tag_case(&self) -> Result<TagCase> {
+        if let Some(case) = self.tag_case.get() {
+            return Ok(case);
+        }
+
+        let name: Option<String> = self
+            .connection
+            .query_row("SELECT value FROM meta WHERE key = 'tag_case'", [], |row| {
+                row.get(0)
+            })
+            .optional()?;
+        let case = match name {
+            Some(name) => TagCase::from_name(&name).ok_or(Error::UnknownTagCase(name))?,
+            None => TagCase::default(),
+        };
+        self.tag_case.set(Some(case));
+        Ok(case)
+    }
+
+    /// Sets the casing that is applied to tag names when tags are created and looked up, e.g. with
+    /// [`TagCase::Lower`] adding the tag `Vacation` creates or finds the tag `vacation`. Existing
+    /// tags are not renamed and can still be found by their exact name.
+    pub fn set_tag_case(&self, case: TagCase) -> Result<()> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO meta(key, value) VALUES ('tag_case', ?1)",
+            (case.name(),),
+        )?;
+        self.tag_case.set(Some(case));
+        Ok(())
+    }
+
+    /// Applies the library's [`TagCase`] to `name`.
+    pub(crate) fn normalize_tag_name(&self, name: &str) -> Result<String> {
+        Ok(self.tag_case()?.apply(name))
+    }
+}
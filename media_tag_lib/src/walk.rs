@@ -0,0 +1,117 @@
+use crate::{IgnoreSet, MediaTag, Result, to_db_string};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Controls how directories are traversed.
+#[derive(Clone, Debug, Default)]
+pub struct WalkOptions {
+    /// How many directory levels to descend; `Some(0)` only visits the files directly inside the
+    /// starting directory. `None` is unlimited.
+    pub max_depth: Option<usize>,
+    /// Descend into symbolic links to directories. Every directory is visited at most once (by
+    /// its canonical path), so links pointing back up the tree do not cause endless loops.
+    pub follow_symlinks: bool,
+}
+
+impl MediaTag {
+    /// Recursively collects the files below `dir`, sorted by path. Hidden entries (starting with
+    /// `.`) and paths excluded by the library's `.mediatagignore` are skipped. Symbolic links to
+    /// files are included, symbolic links to directories are only followed with
+    /// [`WalkOptions::follow_symlinks`].
+    pub fn walk_files<P: AsRef<Path>>(
+        &self,
+        dir: P,
+        options: &WalkOptions,
+    ) -> Result<Vec<PathBuf>> {
+        let dir = dir.as_ref().canonicalize()?;
+        let rel_dir = self.strip_root(&dir)?;
+        let mut walker = Walker {
+            ignore: IgnoreSet::load(self.root()?)?,
+            options,
+            visited: HashSet::from([dir.clone()]),
+        };
+
+        let mut files = Vec::new();
+        walker.walk_dir(&dir, rel_dir, 0, &mut files)?;
+        Ok(files)
+    }
+
+    /// Returns the files inside the library directory that are not tracked in the database.
+    pub fn untracked(&self, options: &WalkOptions) -> Result<Vec<PathBuf>> {
+        let mut stmt = self.connection.prepare("SELECT path FROM media")?;
+        let tracked = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<std::result::Result<HashSet<String>, _>>()?;
+
+        let root = self.root()?;
+        let files = self.walk_files(root, options)?;
+        Ok(files
+            .into_iter()
+            .filter(|file| {
+                file.strip_prefix(root)
+                    .ok()
+                    .and_then(|rel_path| to_db_string(rel_path).ok())
+                    .is_none_or(|rel_path| !tracked.contains(&rel_path))
+            })
+            .collect())
+    }
+}
+
+struct Walker<'a> {
+    ignore: IgnoreSet,
+    options: &'a WalkOptions,
+    /// Canonical paths of the directories walked so far, only tracked when following symbolic
+    /// links
+    visited: HashSet<PathBuf>,
+}
+
+impl Walker<'_> {
+    fn walk_dir(
+        &mut self,
+        dir: &Path,
+        rel_dir: &Path,
+        depth: usize,
+        files: &mut Vec<PathBuf>,
+    ) -> Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            let path = entry.path();
+            let rel_path = rel_dir.join(entry.file_name());
+            let file_type = entry.file_type()?;
+            let is_link_to_dir =
+                self.options.follow_symlinks && file_type.is_symlink() && path.is_dir();
+            let is_dir = file_type.is_dir() || is_link_to_dir;
+            if self.ignore.is_ignored(&rel_path, is_dir) {
+                continue;
+            }
+
+            if is_dir {
+                if self.options.max_depth.is_none_or(|max| depth < max) && self.first_visit(&path) {
+                    self.walk_dir(&path, &rel_path, depth + 1, files)?;
+                }
+            } else if file_type.is_file() || (file_type.is_symlink() && path.is_file()) {
+                files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the directory at `path` has not been walked yet. Always true unless symbolic links
+    /// are followed, as a tree without them cannot contain a directory twice.
+    fn first_visit(&mut self, path: &Path) -> bool {
+        if !self.options.follow_symlinks {
+            return true;
+        }
+        match path.canonicalize() {
+            Ok(canonical) => self.visited.insert(canonical),
+            Err(_) => false,
+        }
+    }
+}